@@ -0,0 +1,31 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use str_var_subst::{replace_variables, Template};
+
+fn container_config_value(var: &str) -> Option<String> {
+    match var {
+        "test_num" => Some(String::from("1")),
+        "test_num_2" => Some(String::from("2")),
+        _ => None,
+    }
+}
+
+fn render_benchmark(c: &mut Criterion) {
+    let template_text = include_str!("../src/test_files/test_template.json.in");
+    let template = Template::parse(template_text);
+
+    let mut group = c.benchmark_group("container_config");
+    group.bench_function("replace_variables", |b| {
+        b.iter(|| replace_variables(black_box(template_text), &container_config_value).unwrap())
+    });
+    group.bench_function("template_render", |b| {
+        b.iter(|| {
+            black_box(&template)
+                .render(&container_config_value)
+                .unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, render_benchmark);
+criterion_main!(benches);
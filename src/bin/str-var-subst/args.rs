@@ -0,0 +1,177 @@
+use crate::check::Format;
+
+const USAGE: &str = "\
+Usage: str-var-subst [OPTIONS] [TEMPLATE]
+       str-var-subst [OPTIONS] --recursive [DIR]
+
+Substitutes %{{VAR}} placeholders in TEMPLATE (or stdin if it is missing or -)
+with values from the environment and writes the result to stdout.
+With --syntax shell, substitutes $VAR and ${VAR} like GNU envsubst instead.
+
+With --recursive, renders every file below DIR (default: the current
+directory) whose name ends with the suffix, to the same path without the
+suffix, e.g. config.json.in to config.json, and prints a line per file.
+Files are written atomically and keep the permissions of their template.
+
+Options:
+  -o, --output <FILE>     Write the result to FILE instead of stdout
+  -i, --in-place          Overwrite the template with the result
+  -r, --recursive         Render every template in a directory tree
+      --suffix <SUFFIX>   Suffix of the templates for --recursive [default: .in]
+      --vars-file <FILE>  Read KEY=VALUE lines from FILE, they take precedence
+                          over the environment. Can be repeated, later files
+                          take precedence over earlier ones
+      --strict            Fail if a variable is unset and has no default,
+                          listing every unset variable
+      --check             Render nothing, report for every placeholder whether
+                          it resolves (and from which source), falls back to
+                          its default, is replaced with an empty string or is
+                          missing. Fails if rendering would fail
+      --format <FORMAT>   Report format of --check: text or json [default: text]
+      --syntax <SYNTAX>   Placeholder syntax: percent for %{{VAR}}, shell for
+                          $VAR and ${VAR} with the :- - := = :+ + :? ?
+                          operators, or both [default: percent]
+      --shell-format <FORMAT>
+                          Only substitute the variables named in FORMAT, e.g.
+                          '$HOME ${USER}', like the SHELL-FORMAT of GNU
+                          envsubst. Other placeholders are kept as they are
+  -h, --help              Print this help
+  -V, --version           Print the version
+
+Exit status:
+  0  success
+  1  a variable could not be substituted
+  2  invalid command line or vars file
+  3  reading the template or writing the result failed
+";
+
+/// Placeholder syntax of `--syntax`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    Percent,
+    Shell,
+    Both,
+}
+
+/// Parsed command line
+#[derive(Debug, PartialEq, Eq)]
+pub struct Args {
+    pub template: Option<String>,
+    pub output: Option<String>,
+    pub vars_files: Vec<String>,
+    pub strict: bool,
+    pub in_place: bool,
+    pub recursive: bool,
+    pub suffix: String,
+    /// `--check` with the report format
+    pub check: Option<Format>,
+    pub syntax: Syntax,
+    pub shell_format: Option<String>,
+}
+
+impl Args {
+    /// Parses the arguments (without the program name).
+    /// Returns `None` if `--help` or `--version` was handled.
+    pub fn parse<I>(args: I) -> Result<Option<Args>, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut parsed = Args {
+            template: None,
+            output: None,
+            vars_files: Vec::new(),
+            strict: false,
+            in_place: false,
+            recursive: false,
+            suffix: String::from(".in"),
+            check: None,
+            syntax: Syntax::Percent,
+            shell_format: None,
+        };
+        let mut check = false;
+        let mut format = Format::Text;
+        let mut args = args.into_iter();
+        let mut only_positional = false;
+
+        while let Some(arg) = args.next() {
+            if only_positional || arg == "-" || !arg.starts_with('-') {
+                if parsed.template.replace(arg).is_some() {
+                    return Err(String::from("only one TEMPLATE can be given"));
+                }
+                continue;
+            }
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => {
+                    (flag.to_owned(), Some(value.to_owned()))
+                }
+                _ => (arg, None),
+            };
+            let mut value = |flag: &str| {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{} requires a value", flag))
+            };
+            match flag.as_str() {
+                "--" => only_positional = true,
+                "-o" | "--output" => parsed.output = Some(value(&flag)?),
+                "--vars-file" => parsed.vars_files.push(value(&flag)?),
+                "--strict" => parsed.strict = true,
+                "-i" | "--in-place" => parsed.in_place = true,
+                "-r" | "--recursive" => parsed.recursive = true,
+                "--suffix" => parsed.suffix = value(&flag)?,
+                "--check" => check = true,
+                "--format" => {
+                    format = match value(&flag)?.as_str() {
+                        "text" => Format::Text,
+                        "json" => Format::Json,
+                        other => return Err(format!("unknown --format {}", other)),
+                    }
+                }
+                "--syntax" => {
+                    parsed.syntax = match value(&flag)?.as_str() {
+                        "percent" => Syntax::Percent,
+                        "shell" => Syntax::Shell,
+                        "both" => Syntax::Both,
+                        other => return Err(format!("unknown --syntax {}", other)),
+                    }
+                }
+                "--shell-format" => parsed.shell_format = Some(value(&flag)?),
+                "-h" | "--help" => {
+                    print!("{}", USAGE);
+                    return Ok(None);
+                }
+                "-V" | "--version" => {
+                    println!("str-var-subst {}", env!("CARGO_PKG_VERSION"));
+                    return Ok(None);
+                }
+                _ => return Err(format!("unknown option {}\n\n{}", flag, USAGE)),
+            }
+        }
+        if check {
+            if parsed.output.is_some() || parsed.in_place {
+                return Err(String::from(
+                    "--check cannot be combined with --output or --in-place",
+                ));
+            }
+            parsed.check = Some(format);
+        }
+        if parsed.output.is_some() && (parsed.in_place || parsed.recursive) {
+            return Err(String::from(
+                "--output cannot be combined with --in-place or --recursive",
+            ));
+        }
+        if parsed.in_place
+            && !parsed.recursive
+            && matches!(parsed.template.as_deref(), None | Some("-"))
+        {
+            return Err(String::from("--in-place requires a TEMPLATE file"));
+        }
+        if parsed.suffix.is_empty() && parsed.recursive && !parsed.in_place {
+            return Err(String::from(
+                "an empty --suffix requires --in-place, templates would overwrite themselves",
+            ));
+        }
+        Ok(Some(parsed))
+    }
+}
@@ -0,0 +1,65 @@
+use crate::Failure;
+use str_var_subst::{replace_variables_json, CheckReport, Layered, Substituter};
+
+/// Report format of `--check`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+/// Checks every template, given as (name, text) pairs, prints the report and
+/// fails if rendering any of them would fail
+pub fn check_templates(
+    templates: &[(String, String)],
+    substituter: &Substituter,
+    resolver: &Layered,
+    strict: bool,
+    format: Format,
+) -> Result<(), Failure> {
+    let reports: Vec<(&str, CheckReport)> = templates
+        .iter()
+        .map(|(name, text)| {
+            let report = substituter.check(&substituter.parse(text), resolver);
+            (name.as_str(), report)
+        })
+        .collect();
+
+    match format {
+        Format::Text => {
+            for (name, report) in &reports {
+                println!("{}:", name);
+                for line in report.to_string().lines() {
+                    println!("  {}", line);
+                }
+            }
+        }
+        Format::Json => {
+            let reports: Vec<String> = reports
+                .iter()
+                .map(|(name, report)| {
+                    let values = [("template", *name), ("variables", &report.to_json())];
+                    replace_variables_json(
+                        r#"{"template": "%{{template}}", "variables": %{{variables}}}"#,
+                        &values,
+                    )
+                    .expect("the report is valid JSON")
+                })
+                .collect();
+            println!("[{}]", reports.join(", "));
+        }
+    }
+
+    let failing = reports
+        .iter()
+        .filter(|(_, report)| report.would_fail(strict))
+        .count();
+    match failing {
+        0 => Ok(()),
+        _ => Err(Failure::Substitution(format!(
+            "{} of {} template(s) would fail to render",
+            failing,
+            reports.len()
+        ))),
+    }
+}
@@ -0,0 +1,71 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Every regular file below `dir` whose name ends with `suffix`, sorted.
+/// Symbolic links are not followed.
+pub fn find_templates(dir: &Path, suffix: &str) -> io::Result<Vec<PathBuf>> {
+    let mut templates = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                pending.push(entry.path());
+            } else if file_type.is_file() && has_suffix(&entry.path(), suffix) {
+                templates.push(entry.path());
+            }
+        }
+    }
+    templates.sort();
+    Ok(templates)
+}
+
+fn has_suffix(path: &Path, suffix: &str) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.len() > suffix.len() && name.ends_with(suffix))
+}
+
+/// Path a template is rendered to: the same path without `suffix`
+pub fn output_path(template: &Path, suffix: &str) -> PathBuf {
+    let path = template.to_string_lossy();
+    PathBuf::from(path.strip_suffix(suffix).unwrap_or(&path))
+}
+
+/// Writes `contents` to a temporary file next to `path` and renames it over
+/// `path`, so readers never see a half-written file. The new file gets the
+/// permissions of `permissions_from`, if given.
+pub fn write_atomically(
+    path: &Path,
+    contents: &str,
+    permissions_from: Option<&Path>,
+) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp_path = path.with_file_name(temp_name);
+
+    // create_new refuses to follow a symlink or reuse a file planted at the
+    // temporary path, and on failure there is nothing of ours to remove
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp_path)?;
+    let result = (|| {
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        if let Some(permissions_from) = permissions_from {
+            fs::set_permissions(&temp_path, fs::metadata(permissions_from)?.permissions())?;
+        }
+        fs::rename(&temp_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
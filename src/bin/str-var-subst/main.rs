@@ -0,0 +1,248 @@
+//! Command-line front end of the library, similar to GNU envsubst but for
+//! the %{{VAR}} syntax, or for `$VAR` and `${VAR}` with `--syntax shell`.
+
+mod args;
+mod check;
+mod files;
+mod vars_file;
+
+use args::{Args, Syntax};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::ExitCode;
+use str_var_subst::{Env, Layered, Substituter};
+
+/// A variable could not be substituted
+const EXIT_SUBSTITUTION: u8 = 1;
+/// Invalid command line or vars file
+const EXIT_USAGE: u8 = 2;
+/// Reading the template or writing the result failed
+const EXIT_IO: u8 = 3;
+
+enum Failure {
+    Substitution(String),
+    Usage(String),
+    Io(String),
+}
+
+impl Failure {
+    fn exit_code(&self) -> u8 {
+        match self {
+            Failure::Substitution(_) => EXIT_SUBSTITUTION,
+            Failure::Usage(_) => EXIT_USAGE,
+            Failure::Io(_) => EXIT_IO,
+        }
+    }
+
+    /// Same kind of failure, i.e. same exit code, with another message
+    fn with_message(self, message: String) -> Failure {
+        match self {
+            Failure::Substitution(_) => Failure::Substitution(message),
+            Failure::Usage(_) => Failure::Usage(message),
+            Failure::Io(_) => Failure::Io(message),
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            Failure::Substitution(message) | Failure::Usage(message) | Failure::Io(message) => {
+                message
+            }
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            eprintln!("str-var-subst: {}", failure.message());
+            ExitCode::from(failure.exit_code())
+        }
+    }
+}
+
+fn run() -> Result<(), Failure> {
+    let args = match Args::parse(std::env::args().skip(1)).map_err(Failure::Usage)? {
+        Some(args) => args,
+        None => return Ok(()),
+    };
+    let resolver = build_resolver(&args.vars_files)?;
+    let substituter = build_substituter(&args);
+
+    if let Some(format) = args.check {
+        let templates = if args.recursive {
+            let dir = args.template.as_deref().unwrap_or(".");
+            read_dir_templates(Path::new(dir), &args.suffix)?
+        } else {
+            let name = match args.template.as_deref() {
+                None | Some("-") => "<stdin>",
+                Some(path) => path,
+            };
+            vec![(name.to_owned(), read_template(args.template.as_deref())?)]
+        };
+        return check::check_templates(&templates, &substituter, &resolver, args.strict, format);
+    }
+
+    if args.recursive {
+        let dir = args.template.as_deref().unwrap_or(".");
+        return render_dir(Path::new(dir), &args, &substituter, &resolver);
+    }
+
+    let template_text = read_template(args.template.as_deref())?;
+    let rendered = render(&template_text, &substituter, &resolver, args.strict)?;
+    let template_path = args.template.as_deref().filter(|path| *path != "-");
+    let output = match (args.in_place, template_path) {
+        (true, Some(path)) => Some(path),
+        _ => args.output.as_deref(),
+    };
+    write_output(output, &rendered, template_path.map(Path::new))
+}
+
+/// Later vars files override earlier ones, all of them override the environment
+fn build_resolver(vars_file_paths: &[String]) -> Result<Layered<'static>, Failure> {
+    let mut vars_files = Vec::new();
+    for path in vars_file_paths {
+        let text = fs::read_to_string(path)
+            .map_err(|e| Failure::Io(format!("cannot read vars file {}: {}", path, e)))?;
+        let vars: HashMap<String, String> = vars_file::parse(&text)
+            .map_err(|e| Failure::Usage(format!("invalid vars file {}: {}", path, e)))?;
+        vars_files.push((path.clone(), vars));
+    }
+    let mut resolver = Layered::new();
+    for (path, vars) in vars_files.into_iter().rev() {
+        resolver = resolver.layer(path, vars);
+    }
+    Ok(resolver.layer("environment", Env))
+}
+
+/// The syntax of `--syntax`, limited to the variables of `--shell-format`
+fn build_substituter(args: &Args) -> Substituter {
+    let mut builder = match args.syntax {
+        Syntax::Percent => Substituter::builder(),
+        Syntax::Shell => Substituter::builder().shell(),
+        Syntax::Both => Substituter::builder().shell_and_default(),
+    };
+    if let Some(shell_format) = &args.shell_format {
+        builder = builder.shell_format(shell_format);
+    }
+    builder.build().expect("the syntaxes of --syntax are valid")
+}
+
+fn render(
+    template_text: &str,
+    substituter: &Substituter,
+    resolver: &Layered,
+    strict: bool,
+) -> Result<String, Failure> {
+    let template = substituter.parse(template_text);
+    let rendered = if strict {
+        template.render_strict(resolver).map_err(|e| e.to_string())
+    } else {
+        template.render(resolver).map_err(|e| e.to_string())
+    };
+    rendered.map_err(Failure::Substitution)
+}
+
+/// Renders every template below `dir`, continuing after failures, and prints
+/// a line per file. Fails with the first failure if any file failed.
+fn render_dir(
+    dir: &Path,
+    args: &Args,
+    substituter: &Substituter,
+    resolver: &Layered,
+) -> Result<(), Failure> {
+    let templates = files::find_templates(dir, &args.suffix)
+        .map_err(|e| Failure::Io(format!("cannot list {}: {}", dir.display(), e)))?;
+
+    let mut first_failure = None;
+    let mut rendered_count = 0;
+    for template in &templates {
+        let output = match args.in_place {
+            true => template.clone(),
+            false => files::output_path(template, &args.suffix),
+        };
+        let result = fs::read_to_string(template)
+            .map_err(|e| Failure::Io(format!("cannot read {}: {}", template.display(), e)))
+            .and_then(|text| render(&text, substituter, resolver, args.strict))
+            .and_then(|rendered| {
+                files::write_atomically(&output, &rendered, Some(template))
+                    .map_err(|e| Failure::Io(format!("cannot write {}: {}", output.display(), e)))
+            });
+        match result {
+            Ok(()) => {
+                rendered_count += 1;
+                println!("rendered {} -> {}", template.display(), output.display());
+            }
+            Err(failure) => {
+                println!("failed   {}", template.display());
+                eprintln!(
+                    "str-var-subst: {}: {}",
+                    template.display(),
+                    failure.message()
+                );
+                first_failure.get_or_insert(failure);
+            }
+        }
+    }
+
+    let summary = format!(
+        "{} template(s) rendered, {} failed",
+        rendered_count,
+        templates.len() - rendered_count
+    );
+    println!("{}", summary);
+    match first_failure {
+        Some(failure) => Err(failure.with_message(summary)),
+        None => Ok(()),
+    }
+}
+
+/// Every template below `dir` as (path, text) pairs
+fn read_dir_templates(dir: &Path, suffix: &str) -> Result<Vec<(String, String)>, Failure> {
+    let templates = files::find_templates(dir, suffix)
+        .map_err(|e| Failure::Io(format!("cannot list {}: {}", dir.display(), e)))?;
+    templates
+        .iter()
+        .map(|template| {
+            let path = template.display().to_string();
+            fs::read_to_string(template)
+                .map(|text| (path.clone(), text))
+                .map_err(|e| Failure::Io(format!("cannot read {}: {}", path, e)))
+        })
+        .collect()
+}
+
+fn read_template(path: Option<&str>) -> Result<String, Failure> {
+    match path {
+        None | Some("-") => {
+            let mut text = String::new();
+            io::stdin()
+                .read_to_string(&mut text)
+                .map_err(|e| Failure::Io(format!("cannot read stdin: {}", e)))?;
+            Ok(text)
+        }
+        Some(path) => fs::read_to_string(path)
+            .map_err(|e| Failure::Io(format!("cannot read {}: {}", path, e))),
+    }
+}
+
+fn write_output(
+    path: Option<&str>,
+    rendered: &str,
+    permissions_from: Option<&Path>,
+) -> Result<(), Failure> {
+    match path {
+        None | Some("-") => {
+            let mut stdout = io::stdout().lock();
+            stdout
+                .write_all(rendered.as_bytes())
+                .and_then(|_| stdout.flush())
+                .map_err(|e| Failure::Io(format!("cannot write stdout: {}", e)))
+        }
+        Some(path) => files::write_atomically(Path::new(path), rendered, permissions_from)
+            .map_err(|e| Failure::Io(format!("cannot write {}: {}", path, e))),
+    }
+}
@@ -0,0 +1,36 @@
+use std::collections::HashMap;
+
+/// Parses `KEY=VALUE` lines. Blank lines and lines starting with `#` are
+/// ignored, an optional `export ` prefix is allowed and values may be wrapped
+/// in single or double quotes.
+pub fn parse(text: &str) -> Result<HashMap<String, String>, String> {
+    let mut vars = HashMap::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected KEY=VALUE", index + 1))?;
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(format!("line {}: missing variable name", index + 1));
+        }
+        vars.insert(key.to_owned(), unquote(value.trim()).to_owned());
+    }
+    Ok(vars)
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|value| value.strip_suffix(quote))
+        {
+            return inner;
+        }
+    }
+    value
+}
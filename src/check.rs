@@ -0,0 +1,595 @@
+use crate::error::ErrorKind;
+use crate::filter::{Filters, BUILTIN};
+use crate::json::json_string;
+use crate::resolver::Resolver;
+use crate::section::{Loop, LoopVariable, SectionKind, Truthiness, DEFAULT_TRUTHINESS};
+use crate::template::{Action, Placeholder, Segment, Settings, Template, VariableRef};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+
+/// What rendering a placeholder would do, see [`check`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Status {
+    /// The resolver provides a value. `source` names where it comes from, if
+    /// the resolver reports it (see [`Resolver::source_of`]).
+    Resolved { source: Option<String> },
+    /// The variable is unset (or empty for `:-`) and the placeholder's
+    /// default value is used
+    Default,
+    /// The variable is unset and has no default, so it is replaced with ""
+    /// (an empty string). Rendering fails in strict mode, except for
+    /// `${variable:+word}`, which is meant to be empty for unset variables.
+    Empty,
+    /// The variable is required with `%{{variable:?message}}` but has no
+    /// value, so rendering fails with the message
+    Missing { message: String },
+    /// The placeholder refers to the items of an `%{{#each}}` section, e.g.
+    /// `%{{@item.source}}` or `%{{@index}}`, and is resolved for every item
+    Loop,
+    /// A filter of the placeholder fails, e.g. it is unknown, so rendering
+    /// fails with the message
+    Failed { message: String },
+}
+
+/// A placeholder of a checked template and what rendering it would do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckEntry {
+    pub variable: VariableRef,
+    pub status: Status,
+}
+
+/// Result of checking a template against a resolver without rendering it.
+/// Displays as a human-readable report with a line per placeholder, see
+/// [`CheckReport::to_json`] for a machine-readable one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckReport {
+    entries: Vec<CheckEntry>,
+    /// For every entry, whether the placeholder is `${variable:+word}`, which
+    /// does not fail in strict mode when it is empty
+    alternative: Vec<bool>,
+}
+
+/// Reports, for every placeholder of the template, whether it would be
+/// resolved (and by which source), fall back to its default, be replaced
+/// with an empty string or make rendering fail. Nothing is rendered.
+/// `%{{#if variable}}` sections are evaluated and the body of an
+/// `%{{#each list}}` section is checked for every item, only the
+/// placeholders of the parts that would be rendered are reported.
+///
+/// Example usage:
+/// ```
+/// use str_var_subst::{check, Layered, Status};
+/// let resolver = Layered::new().layer("cli", [("image", "databroker")]);
+/// let template = "%{{image}}:%{{tag:-latest}} %{{hosts_path:?hosts_path must be set}}";
+/// let report = check(template, &resolver);
+///
+/// let statuses: Vec<_> = report.entries().iter().map(|e| &e.status).collect();
+/// assert_eq!(
+///     statuses,
+///     [
+///         &Status::Resolved { source: Some(String::from("cli")) },
+///         &Status::Default,
+///         &Status::Missing { message: String::from("hosts_path must be set") },
+///     ]
+/// );
+/// assert!(report.would_fail(false));
+/// println!("{}", report);
+/// ```
+///
+pub fn check<R>(template_text: &str, resolver: &R) -> CheckReport
+where
+    R: Resolver + ?Sized,
+{
+    Template::parse(template_text).check(resolver)
+}
+
+impl Template {
+    /// Checks the template against the resolver without rendering it, see [`check`]
+    pub fn check<R>(&self, resolver: &R) -> CheckReport
+    where
+        R: Resolver + ?Sized,
+    {
+        self.check_with(resolver, &BUILTIN, &DEFAULT_TRUTHINESS)
+    }
+
+    /// Same as `check` but filters are applied from the given registry and
+    /// `%{{#if variable}}` sections are evaluated with the given truthiness
+    pub(crate) fn check_with<R>(
+        &self,
+        resolver: &R,
+        filters: &Filters,
+        truthiness: &Truthiness,
+    ) -> CheckReport
+    where
+        R: Resolver + ?Sized,
+    {
+        let mut check = Check {
+            resolver,
+            filters,
+            truthiness,
+            assigned: HashMap::new(),
+            loops: Vec::new(),
+            entries: Vec::new(),
+            alternative: Vec::new(),
+            checked: HashMap::new(),
+        };
+        check.template(self);
+        CheckReport {
+            entries: check.entries,
+            alternative: check.alternative,
+        }
+    }
+}
+
+/// State of checking a template, shared with its sections
+struct Check<'a, R: ?Sized> {
+    resolver: &'a R,
+    filters: &'a Filters,
+    truthiness: &'a Truthiness,
+    /// Values assigned with `${variable:=word}`, they shadow the resolver
+    assigned: HashMap<String, String>,
+    /// The items being checked of the enclosing `%{{#each}}` sections,
+    /// innermost last
+    loops: Vec<Loop>,
+    entries: Vec<CheckEntry>,
+    alternative: Vec<bool>,
+    /// Index of the entry of every placeholder checked so far, by the start
+    /// of its span, the body of an `#each` section is checked once per item
+    checked: HashMap<usize, usize>,
+}
+
+impl<'a, R> Check<'a, R>
+where
+    R: Resolver + ?Sized,
+{
+    /// Checks the placeholders of the parts of the template that would be
+    /// rendered, sections are evaluated like while rendering
+    fn template(&mut self, template: &Template) {
+        for segment in template.segments() {
+            match segment {
+                Segment::Literal(_) => {}
+                Segment::Placeholder(placeholder) => {
+                    let status = self.status(placeholder);
+                    match self.checked.get(&placeholder.span.start) {
+                        // Keep the worst status over all items
+                        Some(&index) => {
+                            if severity(&status) > severity(&self.entries[index].status) {
+                                self.entries[index].status = status;
+                            }
+                        }
+                        None => {
+                            self.checked
+                                .insert(placeholder.span.start, self.entries.len());
+                            self.alternative
+                                .push(placeholder.operator.as_ref().is_some_and(|operator| {
+                                    matches!(operator.action, Action::Alternative)
+                                }));
+                            self.entries.push(CheckEntry {
+                                variable: placeholder.to_variable_ref(),
+                                status,
+                            });
+                        }
+                    }
+                }
+                Segment::Section(section) if section.kind == SectionKind::Each => {
+                    let (list, len) = self.list(&section.condition);
+                    if len == 0 {
+                        self.template(&section.otherwise);
+                    }
+                    for index in 0..len {
+                        self.loops.push(Loop {
+                            list: list.clone(),
+                            index,
+                            len,
+                        });
+                        self.template(&section.body);
+                        self.loops.pop();
+                    }
+                }
+                Segment::Section(section) => {
+                    let value = self.lookup(&section.condition);
+                    let holds = section.holds(value.as_deref(), self.truthiness, || {
+                        self.list(&section.condition).1
+                    });
+                    match holds {
+                        true => self.template(&section.body),
+                        false => self.template(&section.otherwise),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Path and number of items of a list-valued variable
+    fn list(&self, name: &str) -> (String, usize) {
+        let path = match self.loops.last().and_then(|current| current.variable(name)) {
+            Some(LoopVariable::Path(path)) => path,
+            Some(LoopVariable::Value(_)) => return (String::new(), 0),
+            None => name.to_owned(),
+        };
+        let len = self.resolver.list_len(&path).unwrap_or(0);
+        (path, len)
+    }
+
+    /// Value of a variable: a loop variable, a value assigned with
+    /// `${variable:=word}` or the value provided by the resolver
+    fn lookup(&self, name: &str) -> Option<Cow<'a, str>> {
+        let name = match self.loops.last().and_then(|current| current.variable(name)) {
+            Some(LoopVariable::Value(value)) => return value.map(Cow::Owned),
+            Some(LoopVariable::Path(path)) => Cow::Owned(path),
+            None => Cow::Borrowed(name),
+        };
+        match self.assigned.get(name.as_ref()) {
+            Some(value) => Some(Cow::Owned(value.clone())),
+            None => self.resolver.resolve(&name),
+        }
+    }
+
+    fn status(&mut self, placeholder: &Placeholder) -> Status {
+        let resolver = self.resolver;
+        let name = placeholder
+            .variable_name(&|var| Ok(self.lookup(var)), Settings::default())
+            .ok()
+            .flatten();
+        let value = name.as_deref().and_then(|name| self.lookup(name));
+        match &placeholder.operator {
+            Some(operator) if matches!(operator.action, Action::Assign) => {
+                if let (Some(name), Ok(Some(value))) = (&name, operator.apply(value.clone())) {
+                    self.assigned.insert(name.to_string(), value.into_owned());
+                }
+            }
+            None if !placeholder.filters.is_empty() => {
+                if let Err(ErrorKind::Filter { filter, source }) =
+                    self.filters.apply(&placeholder.filters, value.clone())
+                {
+                    return Status::Failed {
+                        message: format!("filter {} failed: {}", filter, source),
+                    };
+                }
+            }
+            _ => {}
+        }
+        match &placeholder.operator {
+            Some(operator) if operator.takes_effect(value.as_deref()) => match operator.action {
+                Action::Default | Action::Assign => Status::Default,
+                Action::Alternative => Status::Empty,
+                Action::Required => Status::Missing {
+                    message: operator.message(),
+                },
+            },
+            None if placeholder.default_filter().is_some()
+                && value.as_deref().is_none_or(str::is_empty) =>
+            {
+                Status::Default
+            }
+            _ if value.is_some() && placeholder.name.starts_with('@') => Status::Loop,
+            _ if value.is_some() => Status::Resolved {
+                source: name
+                    .as_deref()
+                    .and_then(|name| resolver.source_of(name))
+                    .map(str::to_owned),
+            },
+            _ => Status::Empty,
+        }
+    }
+}
+
+/// How bad a status is for rendering, to report the worst status of a
+/// placeholder that is checked several times
+fn severity(status: &Status) -> u8 {
+    match status {
+        Status::Missing { .. } | Status::Failed { .. } => 3,
+        Status::Empty => 2,
+        Status::Default => 1,
+        _ => 0,
+    }
+}
+
+impl CheckReport {
+    /// One entry per placeholder, in the order they appear in the template
+    pub fn entries(&self) -> &[CheckEntry] {
+        &self.entries
+    }
+
+    /// Whether rendering would fail: a required variable is missing, a filter
+    /// fails or, in strict mode, a variable would be replaced with an empty
+    /// string
+    pub fn would_fail(&self, strict: bool) -> bool {
+        let mut entries = self.entries.iter().zip(&self.alternative);
+        entries.any(|(entry, &alternative)| match entry.status {
+            Status::Missing { .. } | Status::Failed { .. } => true,
+            Status::Empty => strict && !alternative,
+            _ => false,
+        })
+    }
+
+    /// Machine-readable report: a JSON array with an object per placeholder,
+    /// e.g. `{"name": "tag", "line": 1, "column": 12, "status": "default"}`.
+    /// Resolved entries may have a `source`, missing and failed ones have a
+    /// `message`.
+    pub fn to_json(&self) -> String {
+        let entries: Vec<String> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let variable = &entry.variable;
+                let mut json = format!(
+                    "{{\"name\": {}, \"line\": {}, \"column\": {}, \"status\": \"{}\"",
+                    json_string(&variable.name),
+                    variable.line,
+                    variable.column,
+                    entry.status.label()
+                );
+                match &entry.status {
+                    Status::Resolved {
+                        source: Some(source),
+                    } => json.push_str(&format!(", \"source\": {}", json_string(source))),
+                    Status::Missing { message } | Status::Failed { message } => {
+                        json.push_str(&format!(", \"message\": {}", json_string(message)))
+                    }
+                    _ => {}
+                }
+                json.push('}');
+                json
+            })
+            .collect();
+        format!("[{}]", entries.join(", "))
+    }
+}
+
+impl Status {
+    fn label(&self) -> &'static str {
+        match self {
+            Status::Resolved { .. } => "resolved",
+            Status::Default => "default",
+            Status::Empty => "empty",
+            Status::Missing { .. } => "missing",
+            Status::Loop => "loop",
+            Status::Failed { .. } => "failed",
+        }
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in &self.entries {
+            let variable = &entry.variable;
+            write!(
+                f,
+                "{}:{}: {} ",
+                variable.line, variable.column, variable.reference
+            )?;
+            match &entry.status {
+                Status::Resolved {
+                    source: Some(source),
+                } => writeln!(f, "resolved from {}", source)?,
+                Status::Resolved { source: None } => writeln!(f, "resolved")?,
+                Status::Default => writeln!(
+                    f,
+                    "uses the default value {:?}",
+                    variable.default.as_deref().unwrap_or_default()
+                )?,
+                Status::Empty => writeln!(f, "is unset, replaced with an empty string")?,
+                Status::Missing { message } => writeln!(f, "is missing: {}", message)?,
+                Status::Loop => writeln!(f, "is resolved for every item of its list")?,
+                Status::Failed { message } => writeln!(f, "fails: {}", message)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::Layered;
+    use crate::Tree;
+
+    #[test]
+    fn test_check_statuses() {
+        let resolver = Layered::new()
+            .layer("cli", [("a", "1")])
+            .layer("defaults", [("b", ""), ("c", "")]);
+        let template = "%{{a}} %{{b}} %{{b:-x}} %{{c-x}}\n%{{d}} %{{d?}} %{{e:?\"e\" is needed}} %{{b | default(\"x\")}}";
+        let report = check(template, &resolver);
+        let statuses: Vec<_> = report
+            .entries()
+            .iter()
+            .map(|e| (e.variable.name.as_str(), e.status.label()))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                ("a", "resolved"),
+                ("b", "resolved"),
+                ("b", "default"),
+                ("c", "resolved"),
+                ("d", "empty"),
+                ("d", "missing"),
+                ("e", "missing"),
+                ("b", "default"),
+            ]
+        );
+        assert!(report.would_fail(false));
+
+        assert_eq!(
+            report.to_string().lines().collect::<Vec<_>>(),
+            [
+                "1:1: %{{a}} resolved from cli",
+                "1:8: %{{b}} resolved from defaults",
+                "1:15: %{{b}} uses the default value \"x\"",
+                "1:25: %{{c}} resolved from defaults",
+                "2:1: %{{d}} is unset, replaced with an empty string",
+                "2:8: %{{d}} is missing: required variable is not set",
+                "2:16: %{{e}} is missing: \"e\" is needed",
+                "2:38: %{{b}} uses the default value \"x\"",
+            ]
+        );
+        assert!(report.to_json().contains(
+            r#"{"name": "e", "line": 2, "column": 16, "status": "missing", "message": "\"e\" is needed"}, "#
+        ));
+    }
+
+    #[test]
+    fn test_check_would_fail() {
+        let report = check("%{{a}} %{{b:-}}", &[("a", "1")]);
+        assert!(!report.would_fail(true));
+        assert_eq!(
+            report.to_json(),
+            r#"[{"name": "a", "line": 1, "column": 1, "status": "resolved"}, {"name": "b", "line": 1, "column": 8, "status": "default"}]"#
+        );
+
+        let report = check(
+            "%{{#each a}}%{{@item}}%{{/each}} %{{#unless b}}%{{a}}%{{/unless}}",
+            &[("a[0]", "1"), ("a", "1")],
+        );
+        let statuses: Vec<_> = report.entries().iter().map(|e| &e.status).collect();
+        assert_eq!(
+            statuses,
+            [&Status::Loop, &Status::Resolved { source: None }]
+        );
+        assert!(!report.would_fail(true));
+
+        let report = check("%{{a}} %{{b}}", &[("a", "1")]);
+        assert!(!report.would_fail(false));
+        assert!(report.would_fail(true));
+        assert_eq!(check("no placeholders", &[("a", "1")]).to_json(), "[]");
+
+        let shell = crate::Substituter::builder().shell().build().unwrap();
+        let report = shell
+            .parse("${a:+set} ${b:+set} ${c+set}")
+            .check(&[("a", "1"), ("c", "")]);
+        let statuses: Vec<_> = report.entries().iter().map(|e| e.status.label()).collect();
+        assert_eq!(statuses, ["resolved", "empty", "resolved"]);
+        assert!(!report.would_fail(true));
+
+        let template = shell.parse("${x:=w} $x ${y:=} $y");
+        let report = shell.check(&template, &[("a", "1")]);
+        let statuses: Vec<_> = report.entries().iter().map(|e| e.status.label()).collect();
+        assert_eq!(statuses, ["default", "resolved", "default", "resolved"]);
+        assert!(!report.would_fail(true));
+        assert!(template.render_strict(&[("a", "1")]).is_ok());
+    }
+
+    #[test]
+    fn test_check_filters() {
+        let report = check("%{{a | nope}} %{{a | upper}} %{{b | nope}}", &[("a", "x")]);
+        assert_eq!(
+            report.entries()[0].status,
+            Status::Failed {
+                message: String::from("filter nope failed: unknown filter")
+            }
+        );
+        assert_eq!(
+            report.entries()[1].status,
+            Status::Resolved { source: None }
+        );
+        // Unknown filters fail even for unset variables, like when rendering
+        assert_eq!(report.entries()[2].status.label(), "failed");
+        assert!(report.would_fail(false));
+        assert_eq!(
+            report.to_string().lines().next(),
+            Some("1:1: %{{a}} fails: filter nope failed: unknown filter")
+        );
+        assert!(report.to_json().starts_with(
+            r#"[{"name": "a", "line": 1, "column": 1, "status": "failed", "message": "filter nope failed: unknown filter"}"#
+        ));
+
+        let filters = crate::Filters::new()
+            .register("nope", |value: &str, _: &[String]| Ok(value.to_owned()));
+        let substituter = crate::Substituter::builder()
+            .filters(filters)
+            .build()
+            .unwrap();
+        let template = substituter.parse("%{{a | nope}} %{{a | missing}}");
+        let report = substituter.check(&template, &[("a", "x")]);
+        assert_eq!(report.entries()[0].status.label(), "resolved");
+        assert_eq!(report.entries()[1].status.label(), "failed");
+        assert!(report.would_fail(false));
+    }
+
+    #[test]
+    fn test_check_conditions() {
+        let template = "%{{#if feature}}%{{feature_url:?must be set}}%{{/if}}";
+        let report = check(template, &[("x", "1")]);
+        assert!(report.entries().is_empty());
+        assert!(!report.would_fail(false));
+        assert!(check(template, &[("feature", "1")]).would_fail(false));
+        assert!(!check(template, &[("feature", "false")]).would_fail(false));
+
+        let template = "%{{#if tls}}%{{cert}}%{{else}}%{{port:-80}}%{{/if}}";
+        let report = check(template, &[("x", "1")]);
+        let names: Vec<_> = report.entries().iter().map(|e| &e.variable.name).collect();
+        assert_eq!(names, ["port"]);
+        assert!(!report.would_fail(true));
+        assert!(check(template, &[("tls", "yes")]).would_fail(true));
+
+        let template = "%{{#unless debug}}%{{key:?needed}}%{{/unless}}";
+        assert!(!check(template, &[("debug", "1")]).would_fail(false));
+        let substituter = crate::Substituter::builder()
+            .truthiness(crate::Truthiness::with_falsy(["off"]))
+            .build()
+            .unwrap();
+        let report = substituter.check(&substituter.parse(template), &[("debug", "0")]);
+        assert!(!report.would_fail(false));
+        assert!(substituter
+            .check(&substituter.parse(template), &[("debug", "off")])
+            .would_fail(false));
+    }
+
+    #[test]
+    fn test_check_each() {
+        let template = "%{{#each ports}}%{{host:?host needed}}%{{/each}}";
+        let report = check(template, &[("x", "1")]);
+        assert!(report.entries().is_empty());
+        assert!(!report.would_fail(true));
+        assert!(check(template, &[("ports[0]", "80")]).would_fail(false));
+
+        let template = "%{{#each ports}}%{{@item}}%{{#if @first}}%{{a}}%{{/if}}%{{#sep}},%{{/sep}}%{{else}}%{{b:?no ports}}%{{/each}}";
+        let resolver = Tree::from_iter([("ports", Tree::from(vec!["80", "443"]))]);
+        let report = check(template, &resolver);
+        let statuses: Vec<_> = report
+            .entries()
+            .iter()
+            .map(|e| (e.variable.name.as_str(), e.status.label()))
+            .collect();
+        assert_eq!(statuses, [("@item", "loop"), ("a", "empty")]);
+        assert!(!report.would_fail(false));
+        let report = check(template, &[("x", "1")]);
+        assert_eq!(report.entries()[0].variable.name, "b");
+        assert!(report.would_fail(false));
+
+        let template = "%{{#each ports}}%{{@item.port:?port needed}}%{{/each}}";
+        let report = check(template, &resolver);
+        assert_eq!(
+            report.entries()[0].status,
+            Status::Missing {
+                message: String::from("port needed")
+            }
+        );
+        assert!(report.would_fail(false));
+        let ports = Tree::from_iter([(
+            "ports",
+            Tree::from(vec![
+                Tree::from_iter([("port", "80")]),
+                Tree::from_iter([("name", "https")]),
+            ]),
+        )]);
+        let statuses: Vec<_> = check(
+            "%{{#each ports}}%{{@item.port:-1}} %{{@item.port}}%{{/each}}",
+            &ports,
+        )
+        .entries()
+        .iter()
+        .map(|e| e.status.label())
+        .collect();
+        assert_eq!(statuses, ["default", "empty"]);
+
+        let template = "%{{#each ports}}%{{#unless @first}}%{{a:?a needed}}%{{/unless}}%{{/each}}";
+        let report = check(template, &resolver);
+        assert_eq!(report.entries().len(), 1);
+        assert!(report.would_fail(false));
+    }
+}
@@ -0,0 +1,340 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+
+/// Boxed error returned by a fallible replacement strategy
+pub type BoxError = Box<dyn StdError + Send + Sync + 'static>;
+
+/// The reason a single %{{variable}} occurrence could not be substituted
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The replacement strategy returned an error for the variable
+    Resolver(BoxError),
+    /// The variable has no value (e.g. it is not set in the environment)
+    Unset,
+    /// A `%{{variable:?message}}` placeholder has no value, carries the message
+    Required(String),
+    /// The value cannot be inserted safely at the placeholder's position in
+    /// the output format, carries the reason
+    Escape(String),
+    /// A filter of a `%{{variable | filter}}` pipeline failed or is unknown
+    Filter { filter: String, source: BoxError },
+    /// Recursive expansion found a value that refers back to a variable it
+    /// is part of, carries the chain of references, e.g. `["a", "b", "a"]`
+    Cycle(Vec<String>),
+    /// Recursive expansion went deeper than the maximum depth, carries the
+    /// maximum and the chain of references
+    DepthExceeded {
+        max_depth: usize,
+        chain: Vec<String>,
+    },
+}
+
+/// Error raised while substituting a %{{variable}} occurrence.
+/// Carries the variable name, the placeholder as written, the byte span of
+/// the whole placeholder (delimiters included) and its line/column in the
+/// template text.
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    name: String,
+    reference: String,
+    span: Range<usize>,
+    line: usize,
+    column: usize,
+}
+
+impl Error {
+    pub(crate) fn new(
+        kind: ErrorKind,
+        name: &str,
+        reference: &str,
+        span: Range<usize>,
+        (line, column): (usize, usize),
+    ) -> Self {
+        Error {
+            kind,
+            name: name.to_owned(),
+            reference: reference.to_owned(),
+            span,
+            line,
+            column,
+        }
+    }
+
+    /// What went wrong
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// Name of the variable, without the %{{ }} delimiters
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The placeholder as written, with its delimiters but without its
+    /// operator and filters, e.g. `%{{name}}` or `${name}`
+    pub fn reference(&self) -> &str {
+        &self.reference
+    }
+
+    /// Byte range of the offending placeholder in the template text
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+
+    /// 1-based line of the placeholder in the template text
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// 1-based column (in characters) of the placeholder in the template text
+    pub fn column(&self) -> usize {
+        self.column
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ErrorKind::Resolver(source) => write!(
+                f,
+                "{}:{}: failed to resolve {}: {}",
+                self.line, self.column, self.reference, source
+            ),
+            ErrorKind::Unset => write!(
+                f,
+                "{}:{}: variable {} is not set",
+                self.line, self.column, self.reference
+            ),
+            ErrorKind::Required(message) => write!(
+                f,
+                "{}:{}: {}: {}",
+                self.line, self.column, self.reference, message
+            ),
+            ErrorKind::Filter { filter, source } => write!(
+                f,
+                "{}:{}: filter {} failed for {}: {}",
+                self.line, self.column, filter, self.reference, source
+            ),
+            ErrorKind::Escape(reason) => write!(
+                f,
+                "{}:{}: cannot insert the value of {}: {}",
+                self.line, self.column, self.reference, reason
+            ),
+            ErrorKind::Cycle(chain) => write!(
+                f,
+                "{}:{}: reference cycle in {}: {}",
+                self.line,
+                self.column,
+                self.reference,
+                chain.join(" -> ")
+            ),
+            ErrorKind::DepthExceeded { max_depth, chain } => write!(
+                f,
+                "{}:{}: {} expands deeper than {} levels: {}",
+                self.line,
+                self.column,
+                self.reference,
+                max_depth,
+                chain.join(" -> ")
+            ),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match &self.kind {
+            ErrorKind::Resolver(source) | ErrorKind::Filter { source, .. } => Some(source.as_ref()),
+            ErrorKind::Unset
+            | ErrorKind::Required(_)
+            | ErrorKind::Escape(_)
+            | ErrorKind::Cycle(_)
+            | ErrorKind::DepthExceeded { .. } => None,
+        }
+    }
+}
+
+/// All the errors collected while substituting a template, in the order
+/// the offending placeholders appear in the text.
+#[derive(Debug)]
+pub struct Errors(pub(crate) Vec<Error>);
+
+impl Errors {
+    /// Iterate over the collected errors
+    pub fn iter(&self) -> std::slice::Iter<'_, Error> {
+        self.0.iter()
+    }
+
+    /// Number of collected errors
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Always false for errors returned by this crate, provided for completeness
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn into_first(self) -> Error {
+        self.0.into_iter().next().expect("Errors is never empty")
+    }
+
+    /// Consume the collection and return the underlying errors
+    pub fn into_vec(self) -> Vec<Error> {
+        self.0
+    }
+}
+
+impl IntoIterator for Errors {
+    type Item = Error;
+    type IntoIter = std::vec::IntoIter<Error>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl fmt::Display for Errors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} variable(s) could not be substituted", self.0.len())?;
+        for err in &self.0 {
+            write!(f, "\n  {}", err)?;
+        }
+        Ok(())
+    }
+}
+
+impl StdError for Errors {}
+
+/// Error raised while substituting variables from a reader into a writer
+#[derive(Debug)]
+pub enum StreamError {
+    /// Reading the template or writing the result failed
+    Io(io::Error),
+    /// A variable could not be substituted
+    Substitution(Error),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StreamError::Io(err) => write!(f, "I/O error: {}", err),
+            StreamError::Substitution(err) => err.fmt(f),
+        }
+    }
+}
+
+impl StdError for StreamError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            StreamError::Io(err) => Some(err),
+            StreamError::Substitution(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for StreamError {
+    fn from(err: io::Error) -> Self {
+        StreamError::Io(err)
+    }
+}
+
+impl From<Error> for StreamError {
+    fn from(err: Error) -> Self {
+        StreamError::Substitution(err)
+    }
+}
+
+/// Invalid delimiters given to a [`SubstituterBuilder`](crate::SubstituterBuilder)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    message: String,
+}
+
+impl SyntaxError {
+    pub(crate) fn new(message: &str) -> Self {
+        SyntaxError {
+            message: message.to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid placeholder syntax: {}", self.message)
+    }
+}
+
+impl StdError for SyntaxError {}
+
+/// Error raised while rendering a template for a specific output format
+#[derive(Debug)]
+pub enum FormatError {
+    /// A variable could not be substituted
+    Substitution(Error),
+    /// The rendered text is not valid in the output format
+    InvalidOutput {
+        /// Name of the output format, e.g. "JSON"
+        format: &'static str,
+        message: String,
+        /// 1-based line of the problem in the rendered text
+        line: usize,
+        /// 1-based column (in characters) of the problem in the rendered text
+        column: usize,
+    },
+}
+
+impl FormatError {
+    pub(crate) fn invalid_output(
+        format: &'static str,
+        message: String,
+        rendered: &str,
+        offset: usize,
+    ) -> Self {
+        let before = &rendered[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        FormatError::InvalidOutput {
+            format,
+            message,
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::Substitution(err) => err.fmt(f),
+            FormatError::InvalidOutput {
+                format,
+                message,
+                line,
+                column,
+            } => write!(
+                f,
+                "rendered {} is invalid at {}:{}: {}",
+                format, line, column, message
+            ),
+        }
+    }
+}
+
+impl StdError for FormatError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            FormatError::Substitution(err) => Some(err),
+            FormatError::InvalidOutput { .. } => None,
+        }
+    }
+}
+
+impl From<Error> for FormatError {
+    fn from(err: Error) -> Self {
+        FormatError::Substitution(err)
+    }
+}
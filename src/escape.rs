@@ -0,0 +1,191 @@
+use crate::error::{Error, Errors};
+use crate::resolver::Resolver;
+use crate::template::{Settings, Template};
+use std::borrow::Cow;
+use std::fmt::Write;
+
+/// Escapes values before they are inserted into a rendered template, see
+/// [`replace_variables_escaped`].
+///
+/// Implemented for the built-in [`ShellQuote`], [`PercentEncode`],
+/// [`XmlEscape`] and [`RegexEscape`], and for closures and functions
+/// `Fn(&str) -> String`.
+pub trait Escaper {
+    /// Returns the value as it should appear in the rendered text
+    fn escape<'v>(&self, value: &'v str) -> Cow<'v, str>;
+}
+
+impl<F> Escaper for F
+where
+    F: Fn(&str) -> String,
+{
+    fn escape<'v>(&self, value: &'v str) -> Cow<'v, str> {
+        Cow::Owned(self(value))
+    }
+}
+
+/// Quotes values as a single POSIX shell word, e.g. `it's` becomes
+/// `'it'\''s'`. Every value is quoted, an empty value becomes `''`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ShellQuote;
+
+impl Escaper for ShellQuote {
+    fn escape<'v>(&self, value: &'v str) -> Cow<'v, str> {
+        Cow::Owned(format!("'{}'", value.replace('\'', r"'\''")))
+    }
+}
+
+/// Percent-encodes values for use in a URL (RFC 3986): everything except
+/// ASCII letters, digits, `-`, `.`, `_` and `~` is encoded as UTF-8 bytes,
+/// e.g. `a b/c` becomes `a%20b%2Fc`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PercentEncode;
+
+impl Escaper for PercentEncode {
+    fn escape<'v>(&self, value: &'v str) -> Cow<'v, str> {
+        let unreserved = |b: u8| b.is_ascii_alphanumeric() || b"-._~".contains(&b);
+        if value.bytes().all(unreserved) {
+            return Cow::Borrowed(value);
+        }
+        let mut encoded = String::with_capacity(value.len() * 3);
+        for b in value.bytes() {
+            if unreserved(b) {
+                encoded.push(char::from(b));
+            } else {
+                write!(encoded, "%{:02X}", b).unwrap();
+            }
+        }
+        Cow::Owned(encoded)
+    }
+}
+
+/// Replaces `&`, `<`, `>`, `"` and `'` with entities, for XML and HTML text
+/// and attribute values
+#[derive(Debug, Default, Clone, Copy)]
+pub struct XmlEscape;
+
+impl Escaper for XmlEscape {
+    fn escape<'v>(&self, value: &'v str) -> Cow<'v, str> {
+        if !value.contains(['&', '<', '>', '"', '\'']) {
+            return Cow::Borrowed(value);
+        }
+        let mut escaped = String::with_capacity(value.len() + 16);
+        for c in value.chars() {
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                '\'' => escaped.push_str("&#39;"),
+                c => escaped.push(c),
+            }
+        }
+        Cow::Owned(escaped)
+    }
+}
+
+/// Escapes regular expression metacharacters so values match literally
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RegexEscape;
+
+impl Escaper for RegexEscape {
+    fn escape<'v>(&self, value: &'v str) -> Cow<'v, str> {
+        match regex::escape(value) {
+            escaped if escaped.len() == value.len() => Cow::Borrowed(value),
+            escaped => Cow::Owned(escaped),
+        }
+    }
+}
+
+/// Same as [`replace_variables`](crate::replace_variables) but every value,
+/// including default values, is passed through the escaper before it is
+/// inserted. The literal text of the template is left as it is.
+///
+/// Example usage:
+/// ```
+/// use str_var_subst::{replace_variables_escaped, PercentEncode, ShellQuote};
+/// let values = [("file", "it's a file.txt")];
+/// let script = replace_variables_escaped("cat %{{file}}", &values, &ShellQuote).unwrap();
+/// assert_eq!(script, r"cat 'it'\''s a file.txt'");
+///
+/// let url = replace_variables_escaped("/files/%{{file}}", &values, &PercentEncode).unwrap();
+/// assert_eq!(url, "/files/it%27s%20a%20file.txt");
+/// ```
+///
+pub fn replace_variables_escaped<R, E>(
+    template_text: &str,
+    resolver: &R,
+    escaper: &E,
+) -> Result<String, Error>
+where
+    R: Resolver + ?Sized,
+    E: Escaper + ?Sized,
+{
+    Template::parse(template_text).render_escaped(resolver, escaper)
+}
+
+impl Template {
+    /// Renders the template, passing every value through the escaper, see
+    /// [`replace_variables_escaped`]
+    pub fn render_escaped<R, E>(&self, resolver: &R, escaper: &E) -> Result<String, Error>
+    where
+        R: Resolver + ?Sized,
+        E: Escaper + ?Sized,
+    {
+        let settings = Settings::for_resolver(&resolver);
+        self.render_with_escape(
+            |var| Ok(resolver.resolve(var)),
+            |_, value| Ok(escaper.escape(value)),
+            settings,
+        )
+        .map_err(Errors::into_first)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    #[test]
+    fn test_builtin_escapers() {
+        let value = "it's <a & b> \"100%\" [x]*?/é";
+        assert_eq!(
+            ShellQuote.escape(value),
+            r#"'it'\''s <a & b> "100%" [x]*?/é'"#
+        );
+        assert_eq!(ShellQuote.escape(""), "''");
+        assert_eq!(
+            PercentEncode.escape(value),
+            "it%27s%20%3Ca%20%26%20b%3E%20%22100%25%22%20%5Bx%5D%2A%3F%2F%C3%A9"
+        );
+        assert_eq!(
+            XmlEscape.escape(value),
+            "it&#39;s &lt;a &amp; b&gt; &quot;100%&quot; [x]*?/é"
+        );
+        assert_eq!(
+            RegexEscape.escape(value),
+            r#"it's <a \& b> "100%" \[x\]\*\?/é"#
+        );
+        for escaper in [&PercentEncode as &dyn Escaper, &XmlEscape, &RegexEscape] {
+            assert!(matches!(escaper.escape("plain_x1"), Cow::Borrowed(_)));
+        }
+    }
+
+    #[test]
+    fn test_render_escaped() {
+        let values = [("pattern", "1+1=2?"), ("empty", "")];
+        let template = Template::parse("^%{{pattern}}%{{empty:-.*}}$");
+        let rendered = template.render_escaped(&values, &RegexEscape).unwrap();
+        assert_eq!(rendered, r"^1\+1=2\?\.\*$");
+        assert!(Regex::new(&rendered).unwrap().is_match("1+1=2?.*"));
+
+        let upper = |value: &str| value.to_uppercase();
+        assert_eq!(
+            replace_variables_escaped("%{{a}}-%{{b}}", &[("a", "x")], &upper).unwrap(),
+            "X-"
+        );
+        let err = replace_variables_escaped("%{{a:?}}", &values, &ShellQuote).unwrap_err();
+        assert_eq!(err.name(), "a");
+    }
+}
@@ -0,0 +1,355 @@
+use crate::error::{BoxError, ErrorKind};
+use crate::escape::{Escaper, ShellQuote};
+use crate::json::json_string;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+
+lazy_static! {
+    static ref FILTER_RE: Regex = Regex::new(
+        r#"\|\s*(?P<name>[a-zA-Z_]\w*)(?:\((?P<args>(?:"(?:[^"\\]|\\.)*"|[^)"])*)\))?"#
+    )
+    .unwrap();
+    static ref ARG_RE: Regex =
+        Regex::new(r#""(?P<string>(?:[^"\\]|\\.)*)"|(?P<number>-?[0-9]+(?:\.[0-9]+)?)"#).unwrap();
+    /// The built-in filters, used when no other registry is given
+    pub(crate) static ref BUILTIN: Filters = Filters::new();
+}
+
+/// Name of the filter that supplies a value for unset or empty variables
+const DEFAULT: &str = "default";
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// A user-defined filter: takes the value and the arguments of the filter
+/// (string or number literals, e.g. `pad(8, "0")`) and returns the new value
+type FilterFn = dyn Fn(&str, &[String]) -> Result<String, BoxError> + Send + Sync;
+
+/// A registry of the filters that can be used in `%{{variable | filter}}`
+/// pipelines, see [`replace_variables_with_filters`](crate::replace_variables_with_filters).
+///
+/// [`Filters::new`] contains the built-in filters:
+/// - `upper`, `lower`: converts the value to upper or lower case
+/// - `trim`: removes leading and trailing whitespace
+/// - `base64`, `base64decode`: encodes the value as standard base64 with
+///   padding, or decodes it (the decoded value must be UTF-8)
+/// - `json`: quotes and escapes the value as a JSON string literal
+/// - `quote`: quotes the value as a single POSIX shell word, see [`ShellQuote`]
+/// - `default("x")`: uses `x` if the variable is unset or empty
+///
+/// Filters are applied from left to right. Except for `default`, they are
+/// skipped for unset variables. `default` cannot be replaced.
+pub struct Filters {
+    filters: HashMap<String, Box<FilterFn>>,
+}
+
+/// A filter in the pipeline of a placeholder, e.g. `default("x")`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FilterCall {
+    pub(crate) name: String,
+    pub(crate) args: Vec<String>,
+}
+
+impl Filters {
+    /// Creates a registry with the built-in filters
+    pub fn new() -> Self {
+        Filters {
+            filters: HashMap::new(),
+        }
+        .register("upper", no_args(|value| Ok(value.to_uppercase())))
+        .register("lower", no_args(|value| Ok(value.to_lowercase())))
+        .register("trim", no_args(|value| Ok(value.trim().to_owned())))
+        .register(
+            "base64",
+            no_args(|value| Ok(base64_encode(value.as_bytes()))),
+        )
+        .register(
+            "base64decode",
+            no_args(|value| Ok(String::from_utf8(base64_decode(value)?)?)),
+        )
+        .register("json", no_args(|value| Ok(json_string(value))))
+        .register(
+            "quote",
+            no_args(|value| Ok(ShellQuote.escape(value).into_owned())),
+        )
+    }
+
+    /// Adds a filter, replacing any filter with the same name
+    pub fn register<F>(mut self, name: impl Into<String>, filter: F) -> Self
+    where
+        F: Fn(&str, &[String]) -> Result<String, BoxError> + Send + Sync + 'static,
+    {
+        self.filters.insert(name.into(), Box::new(filter));
+        self
+    }
+
+    /// Names of the registered filters, in no particular order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.filters.keys().map(String::as_str)
+    }
+
+    /// Passes the value through the filters of a placeholder
+    pub(crate) fn apply<'v>(
+        &self,
+        calls: &[FilterCall],
+        mut value: Option<Cow<'v, str>>,
+    ) -> Result<Option<Cow<'v, str>>, ErrorKind> {
+        for call in calls {
+            let filter_error = |source: BoxError| ErrorKind::Filter {
+                filter: call.name.clone(),
+                source,
+            };
+            if call.name == DEFAULT {
+                let default = match call.args.as_slice() {
+                    [default] => default,
+                    _ => return Err(filter_error("expects 1 argument".into())),
+                };
+                if value.as_deref().is_none_or(str::is_empty) {
+                    value = Some(Cow::Owned(default.clone()));
+                }
+                continue;
+            }
+            let filter = self
+                .filters
+                .get(&call.name)
+                .ok_or_else(|| filter_error("unknown filter".into()))?;
+            if let Some(current) = value {
+                value = Some(Cow::Owned(
+                    filter(&current, &call.args).map_err(filter_error)?,
+                ));
+            }
+        }
+        Ok(value)
+    }
+}
+
+impl Default for Filters {
+    fn default() -> Self {
+        Filters::new()
+    }
+}
+
+impl fmt::Debug for Filters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<_> = self.names().collect();
+        names.sort_unstable();
+        f.debug_struct("Filters").field("filters", &names).finish()
+    }
+}
+
+/// Splits the filters part of a placeholder, e.g. ` | trim | default("x")`.
+/// The text has been validated by the placeholder regex.
+pub(crate) fn parse_pipeline(text: &str) -> Vec<FilterCall> {
+    FILTER_RE
+        .captures_iter(text)
+        .map(|caps| FilterCall {
+            name: caps["name"].to_owned(),
+            args: caps
+                .name("args")
+                .map(|args| {
+                    ARG_RE
+                        .captures_iter(args.as_str())
+                        .map(|arg| match arg.name("string") {
+                            Some(string) => unescape(string.as_str()),
+                            None => arg["number"].to_owned(),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
+        .collect()
+}
+
+/// Resolves the `\"`, `\\`, `\n` and `\t` escapes of a string argument
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Wraps a built-in filter that takes no arguments
+fn no_args<F>(filter: F) -> impl Fn(&str, &[String]) -> Result<String, BoxError>
+where
+    F: Fn(&str) -> Result<String, BoxError>,
+{
+    move |value, args| match args {
+        [] => filter(value),
+        _ => Err("expects no arguments".into()),
+    }
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = (u32::from(chunk[0]) << 16)
+            | (u32::from(chunk.get(1).copied().unwrap_or(0)) << 8)
+            | u32::from(chunk.get(2).copied().unwrap_or(0));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(char::from(
+                    BASE64_ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3f],
+                ));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn base64_decode(text: &str) -> Result<Vec<u8>, BoxError> {
+    let text = text.trim().trim_end_matches('=');
+    if text.len() % 4 == 1 {
+        return Err("invalid base64 length".into());
+    }
+    let mut decoded = Vec::with_capacity(text.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    for c in text.bytes() {
+        let digit = BASE64_ALPHABET
+            .iter()
+            .position(|&d| d == c)
+            .ok_or_else(|| format!("invalid base64 character {:?}", char::from(c)))?;
+        bits = (bits << 6) | digit as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            decoded.push((bits >> bit_count) as u8);
+            bits &= (1 << bit_count) - 1;
+        }
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(pipeline: &str, value: Option<&str>) -> Result<Option<String>, String> {
+        BUILTIN
+            .apply(&parse_pipeline(pipeline), value.map(Cow::Borrowed))
+            .map(|value| value.map(Cow::into_owned))
+            .map_err(|kind| match kind {
+                ErrorKind::Filter { filter, source } => format!("{}: {}", filter, source),
+                kind => panic!("unexpected error {:?}", kind),
+            })
+    }
+
+    #[test]
+    fn test_parse_pipeline() {
+        assert_eq!(
+            parse_pipeline(r#" | trim |default("a\"b)\\") | pad( 8 , "0" )|upper"#),
+            [
+                FilterCall {
+                    name: String::from("trim"),
+                    args: vec![]
+                },
+                FilterCall {
+                    name: String::from("default"),
+                    args: vec![String::from("a\"b)\\")]
+                },
+                FilterCall {
+                    name: String::from("pad"),
+                    args: vec![String::from("8"), String::from("0")]
+                },
+                FilterCall {
+                    name: String::from("upper"),
+                    args: vec![]
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_builtin_filters() {
+        let value = Some("  Héllo, it's me \n");
+        let cases = [
+            ("| upper", "  HÉLLO, IT'S ME \n"),
+            ("| trim | lower", "héllo, it's me"),
+            ("| trim | json", r#""Héllo, it's me""#),
+            ("| trim | quote", r"'Héllo, it'\''s me'"),
+            ("| trim | base64", "SMOpbGxvLCBpdCdzIG1l"),
+            ("| trim | base64 | base64decode", "Héllo, it's me"),
+        ];
+        for (pipeline, expected) in cases {
+            assert_eq!(apply(pipeline, value).unwrap().unwrap(), expected);
+        }
+        for (text, encoded) in [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v")] {
+            assert_eq!(base64_encode(text.as_bytes()), encoded);
+            assert_eq!(base64_decode(encoded).unwrap(), text.as_bytes());
+        }
+        assert_eq!(base64_decode("Zm8").unwrap(), b"fo");
+
+        assert_eq!(
+            apply(r#"| default("x") | upper"#, None).unwrap().unwrap(),
+            "X"
+        );
+        assert_eq!(
+            apply(r#"| trim | default("x")"#, Some(" "))
+                .unwrap()
+                .unwrap(),
+            "x"
+        );
+        assert_eq!(apply("| upper", None).unwrap(), None);
+
+        assert_eq!(
+            apply("| nope", Some("a")).unwrap_err(),
+            "nope: unknown filter"
+        );
+        assert_eq!(
+            apply("| upper(1)", Some("a")).unwrap_err(),
+            "upper: expects no arguments"
+        );
+        assert_eq!(
+            apply("| default", None).unwrap_err(),
+            "default: expects 1 argument"
+        );
+        assert_eq!(
+            apply("| base64decode", Some("Zm9!")).unwrap_err(),
+            "base64decode: invalid base64 character '!'"
+        );
+        assert_eq!(
+            apply("| base64decode", Some("/w=="))
+                .unwrap_err()
+                .split(':')
+                .next(),
+            Some("base64decode")
+        );
+    }
+
+    #[test]
+    fn test_custom_filters() {
+        let filters = Filters::new()
+            .register("pad", |value: &str, args: &[String]| match args {
+                [width, fill] => {
+                    let width: usize = width.parse()?;
+                    let fill = fill.chars().next().ok_or("empty fill")?;
+                    let padding = width.saturating_sub(value.chars().count());
+                    Ok(fill.to_string().repeat(padding) + value)
+                }
+                _ => Err("expects 2 arguments".into()),
+            })
+            .register("upper", |value: &str, _: &[String]| {
+                Ok(format!("<{}>", value))
+            });
+        let pipeline = parse_pipeline(r#"| pad(4, "0") | upper"#);
+        let value = filters.apply(&pipeline, Some(Cow::Borrowed("7"))).unwrap();
+        assert_eq!(value.as_deref(), Some("<0007>"));
+        assert!(format!("{:?}", filters).contains(r#""pad", "quote""#));
+    }
+}
@@ -0,0 +1,468 @@
+use crate::error::{ErrorKind, Errors, FormatError};
+use crate::resolver::Resolver;
+use crate::template::{Segment, Settings, Template};
+use std::borrow::Cow;
+
+/// Maximum nesting of arrays and objects accepted when validating the output
+const MAX_DEPTH: usize = 256;
+
+/// Same as [`replace_variables`](crate::replace_variables) but for JSON
+/// templates. Values of placeholders inside a JSON string literal are escaped
+/// (`"`, `\` and control characters). Values of placeholders outside of
+/// string literals are inserted as they are, e.g. to fill in numbers, but must
+/// be a single JSON value, so they cannot add keys or elements of their own.
+/// Fails if the result is not valid JSON.
+///
+/// Example usage:
+/// ```
+/// use str_var_subst::replace_variables_json;
+/// let template = r#"{"cmd": "%{{cmd}}", "max_files": %{{max_files}}}"#;
+/// let values = [("cmd", "echo \"hi\"\nexit"), ("max_files", "2")];
+/// let rendered = replace_variables_json(template, &values).unwrap();
+/// assert_eq!(rendered, r#"{"cmd": "echo \"hi\"\nexit", "max_files": 2}"#);
+///
+/// let err = replace_variables_json(template, &[("max_files", "two")]).unwrap_err();
+/// assert_eq!(
+///     err.to_string(),
+///     "1:34: cannot insert the value of %{{max_files}}: expected a single JSON value, got \"two\""
+/// );
+/// ```
+///
+pub fn replace_variables_json<R>(template_text: &str, resolver: &R) -> Result<String, FormatError>
+where
+    R: Resolver + ?Sized,
+{
+    Template::parse(template_text).render_json(resolver)
+}
+
+impl Template {
+    /// Renders a JSON template, see [`replace_variables_json`]
+    pub fn render_json<R>(&self, resolver: &R) -> Result<String, FormatError>
+    where
+        R: Resolver + ?Sized,
+    {
+        let in_string = string_contexts(self);
+        let settings = Settings::for_resolver(&resolver);
+        let rendered = self
+            .render_with_escape(
+                |var| Ok(resolver.resolve(var)),
+                |index, value| match in_string[index] {
+                    true => Ok(escape_json(value)),
+                    false => match validate_json(value) {
+                        Ok(()) => Ok(Cow::Borrowed(value)),
+                        Err(_) => Err(ErrorKind::Escape(format!(
+                            "expected a single JSON value, got {}",
+                            json_string(value)
+                        ))),
+                    },
+                },
+                settings,
+            )
+            .map_err(Errors::into_first)?;
+
+        validate_json(&rendered).map_err(|(offset, message)| {
+            FormatError::invalid_output("JSON", message, &rendered, offset)
+        })?;
+        Ok(rendered)
+    }
+}
+
+/// For every placeholder of the template, whether it is inside a JSON string
+/// literal. Only the literal text of the template is taken into account.
+fn string_contexts(template: &Template) -> Vec<bool> {
+    let mut contexts = Vec::new();
+    scan_strings(template, &mut false, &mut false, &mut contexts);
+    contexts
+}
+
+/// Both parts of a `%{{#if variable}}` section start where the section
+/// starts, the text after the section continues where its body ends
+fn scan_strings(
+    template: &Template,
+    in_string: &mut bool,
+    escaped: &mut bool,
+    contexts: &mut Vec<bool>,
+) {
+    for segment in template.segments() {
+        match segment {
+            Segment::Literal(text) => {
+                for c in text.chars() {
+                    match c {
+                        _ if *escaped => *escaped = false,
+                        '\\' if *in_string => *escaped = true,
+                        '"' => *in_string = !*in_string,
+                        _ => {}
+                    }
+                }
+            }
+            Segment::Placeholder(_) => contexts.push(*in_string),
+            Segment::Section(section) => {
+                let (mut otherwise_in_string, mut otherwise_escaped) = (*in_string, *escaped);
+                scan_strings(&section.body, in_string, escaped, contexts);
+                scan_strings(
+                    &section.otherwise,
+                    &mut otherwise_in_string,
+                    &mut otherwise_escaped,
+                    contexts,
+                );
+            }
+        }
+    }
+}
+
+/// Escapes a value for use inside a JSON string literal
+pub(crate) fn escape_json(value: &str) -> Cow<'_, str> {
+    if !value.chars().any(|c| c == '"' || c == '\\' || c < ' ') {
+        return Cow::Borrowed(value);
+    }
+    let mut escaped = String::with_capacity(value.len() + 8);
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c < ' ' => escaped.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Quotes and escapes a string as a JSON string literal
+pub(crate) fn json_string(text: &str) -> String {
+    format!("\"{}\"", escape_json(text))
+}
+
+/// Checks that the text is a single valid JSON value, surrounded by optional
+/// whitespace. On failure returns the byte offset of the problem and a message.
+pub(crate) fn validate_json(text: &str) -> Result<(), (usize, String)> {
+    let mut validator = Validator {
+        bytes: text.as_bytes(),
+        pos: 0,
+    };
+    validator.skip_whitespace();
+    validator.value(0)?;
+    validator.skip_whitespace();
+    match validator.peek() {
+        None => Ok(()),
+        Some(_) => Err(validator.error("trailing characters after the JSON value")),
+    }
+}
+
+struct Validator<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Validator<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn error(&self, message: &str) -> (usize, String) {
+        (self.pos, message.to_owned())
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: u8, message: &str) -> Result<(), (usize, String)> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(self.error(message)),
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<(), (usize, String)> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("arrays and objects are nested too deeply"));
+        }
+        match self.peek() {
+            Some(b'{') => self.object(depth),
+            Some(b'[') => self.array(depth),
+            Some(b'"') => self.string(),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b't') => self.literal("true"),
+            Some(b'f') => self.literal("false"),
+            Some(b'n') => self.literal("null"),
+            Some(_) => Err(self.error("expected a JSON value")),
+            None => Err(self.error("unexpected end of input, expected a JSON value")),
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<(), (usize, String)> {
+        self.pos += 1;
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(());
+        }
+        loop {
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a string as object key"));
+            }
+            self.string()?;
+            self.skip_whitespace();
+            self.expect(b':', "expected ':' after object key")?;
+            self.skip_whitespace();
+            self.value(depth + 1)?;
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => {
+                    self.pos += 1;
+                    self.skip_whitespace();
+                }
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                _ => return Err(self.error("expected ',' or '}' in object")),
+            }
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<(), (usize, String)> {
+        self.pos += 1;
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(());
+        }
+        loop {
+            self.value(depth + 1)?;
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => {
+                    self.pos += 1;
+                    self.skip_whitespace();
+                }
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                _ => return Err(self.error("expected ',' or ']' in array")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<(), (usize, String)> {
+        self.pos += 1;
+        loop {
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't') => {
+                            self.pos += 1
+                        }
+                        Some(b'u') => {
+                            self.pos += 1;
+                            for _ in 0..4 {
+                                match self.peek() {
+                                    Some(c) if c.is_ascii_hexdigit() => self.pos += 1,
+                                    _ => return Err(self.error("invalid \\u escape in string")),
+                                }
+                            }
+                        }
+                        _ => return Err(self.error("invalid escape in string")),
+                    }
+                }
+                Some(c) if c < b' ' => {
+                    return Err(self.error("control character in string must be escaped"))
+                }
+                Some(_) => self.pos += 1,
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<(), (usize, String)> {
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        match self.peek() {
+            Some(b'0') => self.pos += 1,
+            Some(b'1'..=b'9') => self.digits(),
+            _ => return Err(self.error("invalid number")),
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            if !matches!(self.peek(), Some(b'0'..=b'9')) {
+                return Err(self.error("expected digits after '.' in number"));
+            }
+            self.digits();
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            self.pos += 1;
+            if matches!(self.peek(), Some(b'+' | b'-')) {
+                self.pos += 1;
+            }
+            if !matches!(self.peek(), Some(b'0'..=b'9')) {
+                return Err(self.error("expected digits in number exponent"));
+            }
+            self.digits();
+        }
+        Ok(())
+    }
+
+    fn digits(&mut self) {
+        while matches!(self.peek(), Some(b'0'..=b'9')) {
+            self.pos += 1;
+        }
+    }
+
+    fn literal(&mut self, literal: &str) -> Result<(), (usize, String)> {
+        if self.bytes[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            Ok(())
+        } else {
+            Err(self.error("expected a JSON value"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Tree;
+
+    #[test]
+    fn test_json_template_escaping() {
+        let template_text = include_str!("test_files/test_template.json.in");
+        let values = [
+            (
+                "test_num",
+                "quote \" backslash \\ newline \n tab \t bell \u{7}",
+            ),
+            ("test_num_2", "plain"),
+        ];
+        let rendered = replace_variables_json(template_text, &values).unwrap();
+        assert!(rendered
+            .contains(r#""domain_name": "quote \" backslash \\ newline \n tab \t bell \u0007","#));
+        assert!(rendered.contains(r#""hostname_path": "plain","#));
+        assert!(validate_json(&rendered).is_ok());
+    }
+
+    #[test]
+    fn test_json_contexts() {
+        let template = Template::parse(
+            r#"{"a\"%{{x}}": %{{y}}, "b": ["\\", %{{x}}, "%{{x}}\\"], "c": "%%{{x}}"}"#,
+        );
+        assert_eq!(string_contexts(&template), [true, false, false, true]);
+        let rendered = template
+            .render_json(&[("x", "\"q\""), ("y", "[1, 2.5e-3]")])
+            .unwrap();
+        assert_eq!(
+            rendered,
+            r#"{"a\"\"q\"": [1, 2.5e-3], "b": ["\\", "q", "\"q\"\\"], "c": "%{{x}}"}"#
+        );
+
+        let template = Template::parse(
+            r#"{"devices": [%{{#if device}}"%{{device}}"%{{else}}%{{x}}%{{/if}}]%{{#unless m}}, "m": "%{{y}}"%{{/unless}}}"#,
+        );
+        assert_eq!(string_contexts(&template), [true, false, true]);
+        let rendered = template
+            .render_json(&[("device", "/dev/\"tty\""), ("y", "1")])
+            .unwrap();
+        assert_eq!(rendered, r#"{"devices": ["/dev/\"tty\""], "m": "1"}"#);
+        let rendered = template.render_json(&[("x", "1"), ("m", "1")]).unwrap();
+        assert_eq!(rendered, r#"{"devices": [1]}"#);
+
+        let template = Template::parse(
+            r#"[%{{#each ports}}{"host": %{{@item.host}}, "name": "%{{@item.name}}"}%{{#sep}}, %{{/sep}}%{{/each}}]"#,
+        );
+        let ports = Tree::from_iter([(
+            "ports",
+            Tree::from(vec![
+                Tree::from_iter([("host", "80"), ("name", "\"http\"")]),
+                Tree::from_iter([("host", "443"), ("name", "https")]),
+            ]),
+        )]);
+        assert_eq!(
+            template.render_json(&ports).unwrap(),
+            r#"[{"host": 80, "name": "\"http\""}, {"host": 443, "name": "https"}]"#
+        );
+        assert_eq!(template.render_json(&[("a", "1")]).unwrap(), "[]");
+    }
+
+    #[test]
+    fn test_json_invalid_output() {
+        let err = replace_variables_json("{\n  \"a\": %{{a}},\n}", &[("a", "1")]).unwrap_err();
+        match &err {
+            FormatError::InvalidOutput { line, column, .. } => assert_eq!((*line, *column), (3, 1)),
+            err => panic!("unexpected error {:?}", err),
+        }
+        let err = replace_variables_json("%{{a:?a is required}}", &[("b", "")]).unwrap_err();
+        assert!(matches!(err, FormatError::Substitution(_)));
+    }
+
+    #[test]
+    fn test_json_value_injection() {
+        let template = r#"{"max_files": %{{n}}, "privileged": false}"#;
+        let err = replace_variables_json(template, &[("n", r#"2, "privileged": true, "x": 1"#)])
+            .unwrap_err();
+        match err {
+            FormatError::Substitution(err) => {
+                assert!(matches!(err.kind(), ErrorKind::Escape(_)));
+                assert_eq!((err.line(), err.column()), (1, 15));
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+        for value in ["", "1 2", "[1], [2]", "{\"a\": 1} x"] {
+            assert!(replace_variables_json("[%{{v}}]", &[("v", value)]).is_err());
+        }
+        let rendered = replace_variables_json(template, &[("n", " {\"a\": [1, \"b\"]} ")]);
+        assert_eq!(
+            rendered.unwrap(),
+            r#"{"max_files":  {"a": [1, "b"]} , "privileged": false}"#
+        );
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("a\"b\\c\nd\u{1}é"), r#""a\"b\\c\nd\u0001é""#);
+    }
+
+    #[test]
+    fn test_validate_json() {
+        let valid = [
+            "0",
+            " -1.5E+10 ",
+            r#""\u00e9\/""#,
+            "[]",
+            "{}",
+            r#"{"a": [true, false, null, {"b": "ü"}]}"#,
+        ];
+        for text in valid {
+            assert_eq!(validate_json(text), Ok(()), "{}", text);
+        }
+        let invalid = [
+            ("", 0),
+            ("01", 1),
+            ("[1,]", 3),
+            ("{\"a\" 1}", 5),
+            ("\"a\nb\"", 2),
+            ("\"\\x\"", 2),
+            ("tru", 0),
+            ("{} {}", 3),
+            ("1.", 2),
+            ("[", 1),
+        ];
+        for (text, offset) in invalid {
+            assert_eq!(validate_json(text).unwrap_err().0, offset, "{:?}", text);
+        }
+        assert!(validate_json(&"[".repeat(MAX_DEPTH + 2)).is_err());
+    }
+}
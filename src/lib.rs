@@ -1,71 +1,321 @@
-use lazy_static::lazy_static;
-use regex::{Captures, Regex};
-use std::env;
+mod check;
+mod error;
+mod escape;
+mod filter;
+mod json;
+mod recursive;
+mod resolver;
+mod section;
+mod stream;
+mod substituter;
+mod template;
+mod tree;
+#[cfg(feature = "serde_json")]
+mod typed;
+#[cfg(feature = "yaml")]
+mod yaml;
 
-lazy_static! {
-    static ref RE: Regex = Regex::new(r"(%\{\{)([a-zA-Z_]\w*)(\}\})").unwrap();
-}
+pub use check::{check, CheckEntry, CheckReport, Status};
+pub use error::{BoxError, Error, ErrorKind, Errors, FormatError, StreamError, SyntaxError};
+pub use escape::{
+    replace_variables_escaped, Escaper, PercentEncode, RegexEscape, ShellQuote, XmlEscape,
+};
+pub use filter::Filters;
+pub use json::replace_variables_json;
+pub use recursive::replace_variables_recursive;
+pub use resolver::{Env, Layered, Resolver};
+pub use section::Truthiness;
+pub use stream::replace_variables_stream;
+pub use substituter::{Substituter, SubstituterBuilder};
+pub use template::{Template, VariableRef};
+pub use tree::Tree;
+#[cfg(feature = "serde_json")]
+pub use typed::replace_variables_value;
+#[cfg(feature = "yaml")]
+pub use yaml::{replace_variables_yaml, replace_variables_yaml_typed};
+
+use std::borrow::Cow;
+use std::env;
+use template::Settings;
 
 /// Replaces variables in strings in the format %{{variable}}
-/// Takes the template text as an input and a "replacement strategy" function
-/// that provides the mapping between %{{variable}} and its value.
+/// Takes the template text as an input and a [`Resolver`] that provides the
+/// mapping between %{{variable}} and its value, e.g. a function, a map or
+/// the process environment.
 /// The delimiting character %, { and } are stripped before passing to the
-/// mapping function
+/// resolver
 ///
 /// Example usage:
 /// ```
 /// use str_var_subst::replace_variables;
 /// let test_str = "Hi my name is %{{name}}%{{no_var}}!";
-/// let parsed_str = replace_variables(test_str, |var| {
+/// let parsed_str = replace_variables(test_str, &|var: &str| {
 ///     if var == "name" {
 ///         return String::from("John")
 ///     } else {
 ///         return String::from("") // e.g. %{{no_var}} gets mapped to the empty string
 ///     }
-/// });
+/// }).unwrap();
 /// assert_eq!(parsed_str, "Hi my name is John!");
 /// println!("{}", parsed_str); // Hi my name is John!
 /// ```
 ///
-pub fn replace_variables<F>(template_text: &str, replacement_strategy: F) -> String
+/// Placeholders may carry a shell-style default value or be marked as required:
+/// - `%{{variable:-default}}` uses `default` if the value is unset or empty
+/// - `%{{variable-default}}` uses `default` only if the value is unset
+/// - `%{{variable:?message}}` fails with `message` if the value is unset or empty
+/// - `%{{variable?message}}` fails with `message` only if the value is unset
+///
+/// Unset variables without a default are replaced with "" (an empty string).
+/// A literal `%{{` can be written as `%%{{`, e.g. `%%{{variable}}` is
+/// rendered as `%{{variable}}`.
+/// Names may be dotted and indexed paths, e.g. `%{{host_config.mounts[0]}}`,
+/// which resolvers of nested data such as [`Tree`] follow. Other resolvers
+/// look them up as they are.
+/// Names can be computed: in `%{{db_url_%{{env}}}}` the nested placeholder
+/// is substituted first (one level deep), and `%{{!ptr}}` substitutes the
+/// variable named by the value of `ptr`.
+///
+/// ```
+/// use str_var_subst::replace_variables;
+/// let test_str = "%{{greeting-Hello}}, %{{name:-stranger}}%{{suffix-!}}";
+/// let values = [("name", ""), ("suffix", "?")];
+/// let parsed_str = replace_variables(test_str, &values).unwrap();
+/// assert_eq!(parsed_str, "Hello, stranger?");
+/// ```
+///
+/// Values can be passed through a pipeline of filters instead, e.g.
+/// `%{{name | trim | upper}}` or `%{{tag | default("latest")}}`, see
+/// [`Filters`] for the built-in ones. A placeholder has either an operator or
+/// filters: in `%{{name:-a | upper}}` the default value is `a | upper`.
+///
+/// ```
+/// use str_var_subst::replace_variables;
+/// let test_str = "%{{name | trim | upper}}:%{{tag | default(\"latest\")}}";
+/// let parsed_str = replace_variables(test_str, &[("name", " databroker ")]).unwrap();
+/// assert_eq!(parsed_str, "DATABROKER:latest");
+/// ```
+///
+/// Parts of a template can be included or left out depending on a variable
+/// with `%{{#if variable}}...%{{else}}...%{{/if}}` and
+/// `%{{#unless variable}}...%{{/unless}}`, the `%{{else}}` part being
+/// optional. Unset variables and the values `""`, `"0"` and `"false"` (in
+/// any case) are false, see [`Truthiness`] to change that. Tags without a
+/// matching opening or closing tag are kept as literal text.
+///
+/// ```
+/// use str_var_subst::replace_variables;
+/// let test_str = r#"{"image": "%{{image}}"%{{#if device}}, "devices": ["%{{device}}"]%{{/if}}}"#;
+/// let parsed_str = replace_variables(test_str, &[("image", "databroker")]).unwrap();
+/// assert_eq!(parsed_str, r#"{"image": "databroker"}"#);
+/// ```
+///
+/// `%{{#each list}}...%{{/each}}` repeats its body for every item of a
+/// list-valued variable (see [`Resolver::list_len`]) and renders its
+/// `%{{else}}` part if the list is empty. In the body, `%{{@item}}` is the
+/// current item, `%{{@item.source}}` a part of it, `%{{@index}}` its 0-based
+/// index, and `%{{@first}}` and `%{{@last}}` are `true` for the first and
+/// last item and unset otherwise. `%{{#sep}}...%{{/sep}}` is rendered for
+/// every item but the last one, e.g. to separate the items with commas.
+///
+/// ```
+/// use str_var_subst::{replace_variables, Tree};
+/// let test_str = r#"[%{{#each ports}}"%{{@item}}"%{{#sep}}, %{{/sep}}%{{/each}}]"#;
+/// let values = Tree::from_iter([("ports", Tree::from(vec!["80:80", "443:443"]))]);
+/// let parsed_str = replace_variables(test_str, &values).unwrap();
+/// assert_eq!(parsed_str, r#"["80:80", "443:443"]"#);
+/// ```
+///
+/// Returns an error for the first required variable that has no value, or
+/// the first filter that fails.
+pub fn replace_variables<R>(template_text: &str, resolver: &R) -> Result<String, Error>
 where
-    F: Fn(&str) -> String,
+    R: Resolver + ?Sized,
 {
-    let result = RE.replace_all(template_text, |caps: &Captures| {
-        format!("{}", replacement_strategy(&remove_var_delimiters(&caps[0])))
-    });
+    Template::parse(template_text).render(resolver)
+}
 
-    String::from(result.to_string())
+/// Same as [`replace_variables`] but with user-defined filters, in addition
+/// to or instead of the built-in ones.
+///
+/// Example usage:
+/// ```
+/// use str_var_subst::{replace_variables_with_filters, Filters};
+/// let filters = Filters::new().register("reverse", |value: &str, _args: &[String]| {
+///     Ok(value.chars().rev().collect())
+/// });
+/// let template = "%{{name | reverse | upper}}";
+/// let parsed_str = replace_variables_with_filters(template, &[("name", "abc")], &filters).unwrap();
+/// assert_eq!(parsed_str, "CBA");
+/// ```
+///
+pub fn replace_variables_with_filters<R>(
+    template_text: &str,
+    resolver: &R,
+    filters: &Filters,
+) -> Result<String, Error>
+where
+    R: Resolver + ?Sized,
+{
+    Template::parse(template_text).render_with_filters(resolver, filters)
 }
 
-fn remove_var_delimiters(raw_variable: &str) -> String {
-    raw_variable
-        .replace("{", "")
-        .replace("}", "")
-        .replace("%", "")
-        .trim()
-        .to_owned()
+/// Same as [`replace_variables`] but with a replacement strategy that can fail.
+/// Substitution stops at the first variable for which the strategy returns an
+/// error, and that error is returned together with the variable name and the
+/// byte span of the offending %{{variable}}.
+/// As with closures used as [`Resolver`], there are no list-valued variables,
+/// so `%{{#each list}}` sections render their `%{{else}}` part.
+///
+/// Example usage:
+/// ```
+/// use str_var_subst::try_replace_variables;
+/// let test_str = "Hi my name is %{{name}} and my password is %{{password}}";
+/// let err = try_replace_variables(test_str, |var| match var {
+///     "name" => Ok(String::from("John")),
+///     _ => Err(format!("{} is a secret", var)),
+/// })
+/// .unwrap_err();
+/// assert_eq!(err.name(), "password");
+/// assert_eq!(&test_str[err.span()], "%{{password}}");
+/// ```
+///
+pub fn try_replace_variables<F, E>(
+    template_text: &str,
+    replacement_strategy: F,
+) -> Result<String, Error>
+where
+    F: Fn(&str) -> Result<String, E>,
+    E: Into<BoxError>,
+{
+    let settings = Settings {
+        fail_fast: true,
+        ..Settings::default()
+    };
+    Template::parse(template_text)
+        .render_with(resolver_errors(replacement_strategy), settings)
+        .map_err(Errors::into_first)
+}
+
+/// Same as [`try_replace_variables`] but does not stop at the first failure.
+/// Every variable is passed to the replacement strategy and all the errors
+/// are returned at once, in the order they appear in the template.
+pub fn try_replace_variables_all<F, E>(
+    template_text: &str,
+    replacement_strategy: F,
+) -> Result<String, Errors>
+where
+    F: Fn(&str) -> Result<String, E>,
+    E: Into<BoxError>,
+{
+    Template::parse(template_text)
+        .render_with(resolver_errors(replacement_strategy), Settings::default())
+}
+
+fn resolver_errors<F, E>(
+    replacement_strategy: F,
+) -> impl Fn(&str) -> Result<Option<Cow<'static, str>>, ErrorKind>
+where
+    F: Fn(&str) -> Result<String, E>,
+    E: Into<BoxError>,
+{
+    move |var| {
+        replacement_strategy(var)
+            .map(|value| Some(Cow::Owned(value)))
+            .map_err(|e| ErrorKind::Resolver(e.into()))
+    }
+}
+
+/// Lists the %{{variable}} placeholders of a template with their location,
+/// in the order they appear in the text
+///
+/// Example usage:
+/// ```
+/// use str_var_subst::variables;
+/// let template = "{\n  \"name\": \"%{{name}}\",\n  \"tag\": \"%{{tag:-latest}}\"\n}";
+/// let names: Vec<_> = variables(template)
+///     .into_iter()
+///     .map(|var| format!("{} at {}:{}", var.name, var.line, var.column))
+///     .collect();
+/// assert_eq!(names, ["name at 2:12", "tag at 3:11"]);
+/// ```
+///
+pub fn variables(template_text: &str) -> Vec<VariableRef> {
+    Template::parse(template_text).variables()
 }
 
 /// Replace a variable in a string with its value from the environment
 /// If the variable is unset it is replaced with "" (an empty string).
 pub fn map_to_env(var: &str) -> String {
-    match env::var(var) {
-        Ok(val) => val,
-        Err(_) => String::from(""),
-    }
+    env::var(var).unwrap_or_default()
 }
 
 /// Directly replace environmental variables in a template
-/// If the variable is unset it is replaced with "" (an empty string).
-pub fn envsubst(template_text: &str) -> String {
-    replace_variables(&template_text, map_to_env)
+/// If the variable is unset it is replaced with its default value, if the
+/// placeholder has one, or with "" (an empty string).
+/// Fails if a variable marked as required with `%{{variable:?message}}` is unset.
+pub fn envsubst(template_text: &str) -> Result<String, Error> {
+    replace_variables(template_text, &Env)
+}
+
+/// Drop-in replacement for GNU envsubst: replaces `$VAR` and `${VAR}` with
+/// values from the environment, see [`SubstituterBuilder::shell`] for the
+/// supported operators. With a SHELL-FORMAT, e.g. `"$HOME $USER"`, only the
+/// variables named in it are replaced and other references are kept.
+///
+/// Example usage:
+/// ```
+/// use str_var_subst::envsubst_shell;
+/// let rendered = envsubst_shell("${STR_VAR_SUBST_DOC_UNSET:-none} $PATH", Some("${STR_VAR_SUBST_DOC_UNSET}"));
+/// assert_eq!(rendered.unwrap(), "none $PATH");
+/// ```
+///
+pub fn envsubst_shell(template_text: &str, shell_format: Option<&str>) -> Result<String, Error> {
+    let mut builder = Substituter::builder().shell();
+    if let Some(shell_format) = shell_format {
+        builder = builder.shell_format(shell_format);
+    }
+    builder
+        .build()
+        .expect("the shell syntax is valid")
+        .replace(template_text, &Env)
+}
+
+/// Like [`envsubst`] but fails instead of substituting an empty string.
+/// Every variable that is unset and has no default value, every required
+/// variable without a value and every variable whose value is not valid
+/// unicode is reported with its line and column in the template.
+///
+/// Example usage:
+/// ```
+/// use str_var_subst::envsubst_strict;
+/// let template = "{\n  \"hosts_path\": \"%{{STR_VAR_SUBST_DOC_UNSET}}\"\n}";
+/// let errors = envsubst_strict(template).unwrap_err();
+/// let err = errors.iter().next().unwrap();
+/// assert_eq!(err.name(), "STR_VAR_SUBST_DOC_UNSET");
+/// assert_eq!((err.line(), err.column()), (2, 18));
+/// ```
+///
+pub fn envsubst_strict(template_text: &str) -> Result<String, Errors> {
+    Template::parse(template_text).render_with(
+        |var| match env::var(var) {
+            Ok(val) => Ok(Some(Cow::Owned(val))),
+            Err(env::VarError::NotPresent) => Ok(None),
+            Err(e) => Err(ErrorKind::Resolver(e.into())),
+        },
+        Settings {
+            error_on_unset: true,
+            ..Settings::default()
+        },
+    )
 }
 
 #[cfg(test)]
 mod tests {
-    static TEST_EXPR: &'static str = "This is a test string that has %{{test_num}} %{{test_num_2}}%{{test_num}} %{{test_num_2}} %{{empty_var}}variables";
+    static TEST_EXPR: &str = "This is a test string that has %{{test_num}} %{{test_num_2}}%{{test_num}} %{{test_num_2}} %{{empty_var}}variables";
     use crate::*;
+    use std::collections::HashMap;
+    use std::convert::Infallible;
     fn one_two_replace(variable: &str) -> String {
         if variable == "test_num" {
             return String::from("1");
@@ -73,11 +323,11 @@ mod tests {
         if variable == "test_num_2" {
             return String::from("2");
         }
-        return String::from("");
+        String::from("")
     }
     #[test]
     fn test_simple_replacement() {
-        let res = replace_variables(TEST_EXPR, one_two_replace);
+        let res = replace_variables(TEST_EXPR, &one_two_replace).unwrap();
         assert_eq!(
             res,
             String::from("This is a test string that has 1 21 2 variables")
@@ -91,19 +341,136 @@ mod tests {
         let val = "environment";
         let template = format!("This string uses a value from the %{{{{{}}}}}", key);
         env::set_var(key, val);
-        let res = envsubst(&template);
+        let res = envsubst(&template).unwrap();
         env::remove_var(key);
         assert_eq!(res, "This string uses a value from the environment");
         println!("{}", res);
     }
 
+    #[test]
+    fn test_env_subst_strict() {
+        let key = "STR_VAR_SUBST_TEST_STRICT_VAR";
+        let template = format!(
+            "%{{{{{}}}}} is set\n  but %{{{{STR_VAR_SUBST_UNSET_1}}}} and\n%{{{{STR_VAR_SUBST_UNSET_2}}}} are not",
+            key
+        );
+        env::set_var(key, "this");
+        let res = envsubst_strict(&template);
+        env::remove_var(key);
+
+        let errors = res.unwrap_err();
+        let unset: Vec<(&str, usize, usize)> = errors
+            .iter()
+            .map(|e| (e.name(), e.line(), e.column()))
+            .collect();
+        assert_eq!(
+            unset,
+            [
+                ("STR_VAR_SUBST_UNSET_1", 2, 7),
+                ("STR_VAR_SUBST_UNSET_2", 3, 1)
+            ]
+        );
+        assert!(errors.iter().all(|e| matches!(e.kind(), ErrorKind::Unset)));
+        println!("{}", errors);
+
+        env::set_var(key, "this");
+        let res = envsubst_strict(&format!("%{{{{{}}}}} is set", key));
+        env::remove_var(key);
+        assert_eq!(res.unwrap(), "this is set");
+    }
+
+    #[test]
+    fn test_default_values() {
+        let template =
+            "[%{{test_num:-x}}][%{{empty_var:-x}}][%{{empty_var-x}}][%{{unset:-x}}][%{{unset-}}]";
+        let values = HashMap::from([("test_num", "1"), ("empty_var", "")]);
+        let res = replace_variables(template, &values).unwrap();
+        assert_eq!(res, "[1][x][][x][]");
+
+        // every value of a resolver returning String counts as set
+        let res = replace_variables(template, &one_two_replace).unwrap();
+        assert_eq!(res, "[1][x][][x][]");
+        let res = replace_variables("%{{no_var-default}}", &one_two_replace).unwrap();
+        assert_eq!(res, "");
+    }
+
+    #[test]
+    fn test_default_values_span_and_env() {
+        let template = "%{{STR_VAR_SUBST_DEFAULT_UNSET:-multi\nline: {value} }}%{{STR_VAR_SUBST_DEFAULT_UNSET-}}.";
+        assert_eq!(envsubst(template).unwrap(), "multi\nline: {value} .");
+        assert_eq!(envsubst_strict(template).unwrap(), "multi\nline: {value} .");
+    }
+
+    #[test]
+    fn test_required_values() {
+        let template = "{\n  \"hosts_path\": \"%{{no_var:?hosts_path must be configured}}\"\n}";
+        let err = replace_variables(template, &one_two_replace).unwrap_err();
+        assert_eq!(err.name(), "no_var");
+        assert_eq!((err.line(), err.column()), (2, 18));
+        match err.kind() {
+            ErrorKind::Required(message) => assert_eq!(message, "hosts_path must be configured"),
+            kind => panic!("unexpected error kind {:?}", kind),
+        }
+        assert_eq!(
+            err.to_string(),
+            "2:18: %{{no_var}}: hosts_path must be configured"
+        );
+
+        let res = replace_variables("%{{test_num:?}}%{{empty_var?}}", &one_two_replace);
+        assert_eq!(res.unwrap(), "1");
+        let err = replace_variables("%{{no_var?}}", &[("test_num", "1")]).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::Required(_)));
+
+        let errors = envsubst_strict(
+            "%{{STR_VAR_SUBST_REQUIRED_UNSET:?set me}} %{{STR_VAR_SUBST_REQUIRED_UNSET}}",
+        )
+        .unwrap_err();
+        let kinds: Vec<String> = errors.iter().map(|e| format!("{:?}", e.kind())).collect();
+        assert_eq!(kinds, [r#"Required("set me")"#, "Unset"]);
+        assert!(envsubst("%{{STR_VAR_SUBST_REQUIRED_UNSET:?set me}}").is_err());
+    }
+
     #[test]
     fn test_json_template() {
         let in_template = include_str!("test_files/test_template.json.in");
         let expected_output = include_str!("test_files/test_output.json.in");
-        let parsed = replace_variables(in_template, one_two_replace);
+        let parsed = replace_variables(in_template, &one_two_replace).unwrap();
         println!("{}", parsed);
         assert_ne!(in_template, expected_output);
         assert_eq!(parsed, expected_output);
     }
+
+    #[test]
+    fn test_try_replace_stops_at_first_error() {
+        let res = try_replace_variables(TEST_EXPR, |var| match var {
+            "empty_var" => Ok(String::new()),
+            _ => Err(format!("{} is not allowed", var)),
+        });
+        let err = res.unwrap_err();
+        assert_eq!(err.name(), "test_num");
+        assert_eq!(&TEST_EXPR[err.span()], "%{{test_num}}");
+        assert!(matches!(err.kind(), ErrorKind::Resolver(_)));
+        assert!(err.to_string().contains("test_num is not allowed"));
+    }
+
+    #[test]
+    fn test_try_replace_collects_all_errors() {
+        let errors = try_replace_variables_all(TEST_EXPR, |var| match var {
+            "test_num" => Ok(String::from("1")),
+            _ => Err(std::fmt::Error),
+        })
+        .unwrap_err();
+        let names: Vec<&str> = errors.iter().map(|e| e.name()).collect();
+        assert_eq!(names, ["test_num_2", "test_num_2", "empty_var"]);
+        for err in errors.iter() {
+            assert_eq!(&TEST_EXPR[err.span()], format!("%{{{{{}}}}}", err.name()));
+        }
+
+        let ok =
+            try_replace_variables_all(TEST_EXPR, |var| Ok::<_, Infallible>(one_two_replace(var)));
+        assert_eq!(
+            ok.unwrap(),
+            "This is a test string that has 1 21 2 variables"
+        );
+    }
 }
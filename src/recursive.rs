@@ -0,0 +1,174 @@
+use crate::error::{Error, ErrorKind, Errors};
+use crate::resolver::Resolver;
+use crate::template::{Position, Settings, Syntax, Template, DEFAULT_SYNTAX};
+use std::borrow::Cow;
+
+/// Same as [`replace_variables`](crate::replace_variables) but values that
+/// contain placeholders themselves are expanded too, until no placeholders
+/// are left. Placeholders in a value use the same operators and filters as
+/// the template, and `%%{{` in a value is rendered as a literal `%{{`.
+///
+/// A value that refers back to a variable it is part of fails with
+/// [`ErrorKind::Cycle`], and values nested more than `max_depth` levels
+/// deep fail with [`ErrorKind::DepthExceeded`], both with the chain of
+/// references. The error is reported at the placeholder of the template.
+///
+/// Example usage:
+/// ```
+/// use str_var_subst::replace_variables_recursive;
+/// let values = [
+///     ("url", "http://%{{host}}:%{{port:-80}}/"),
+///     ("host", "%{{name}}.example.com"),
+///     ("name", "databroker"),
+///     ("loop", "%{{again}}"),
+///     ("again", "x %{{loop}}"),
+/// ];
+/// let rendered = replace_variables_recursive("%{{url}}", &values, 8).unwrap();
+/// assert_eq!(rendered, "http://databroker.example.com:80/");
+///
+/// let err = replace_variables_recursive("%{{loop}}", &values, 8).unwrap_err();
+/// assert_eq!(err.to_string(), "1:1: reference cycle in %{{loop}}: loop -> again -> loop");
+/// let err = replace_variables_recursive("%{{url}}", &values, 1).unwrap_err();
+/// assert_eq!(err.to_string(), "1:1: %{{url}} expands deeper than 1 levels: url -> host");
+/// ```
+///
+pub fn replace_variables_recursive<R>(
+    template_text: &str,
+    resolver: &R,
+    max_depth: usize,
+) -> Result<String, Error>
+where
+    R: Resolver + ?Sized,
+{
+    Template::parse(template_text).render_recursive(resolver, max_depth)
+}
+
+impl Template {
+    /// Renders the template, expanding placeholders in values up to
+    /// `max_depth` levels deep, see [`replace_variables_recursive`]
+    pub fn render_recursive<R>(&self, resolver: &R, max_depth: usize) -> Result<String, Error>
+    where
+        R: Resolver + ?Sized,
+    {
+        let settings = Settings::for_resolver(&resolver);
+        let expansion = Expansion {
+            syntax: &DEFAULT_SYNTAX,
+            max_depth,
+            settings,
+        };
+        self.render_with(|var| expansion.resolve(resolver, var, &[]), settings)
+            .map_err(Errors::into_first)
+    }
+}
+
+/// How the placeholders in values are expanded
+pub(crate) struct Expansion<'s> {
+    pub(crate) syntax: &'s Syntax,
+    pub(crate) max_depth: usize,
+    /// Settings of the template, also used for the values
+    pub(crate) settings: Settings<'s>,
+}
+
+impl Expansion<'_> {
+    /// Resolves the variable and expands the placeholders in its value.
+    /// `chain` lists the variables whose values are being expanded.
+    pub(crate) fn resolve<'r, R>(
+        &self,
+        resolver: &'r R,
+        name: &str,
+        chain: &[&str],
+    ) -> Result<Option<Cow<'r, str>>, ErrorKind>
+    where
+        R: Resolver + ?Sized,
+    {
+        let chain_to = |name: &str| {
+            chain
+                .iter()
+                .copied()
+                .chain([name])
+                .map(str::to_owned)
+                .collect()
+        };
+        if chain.contains(&name) {
+            return Err(ErrorKind::Cycle(chain_to(name)));
+        }
+        let value = match resolver.resolve(name) {
+            Some(value) if self.syntax.re.is_match(&value) => value,
+            value => return Ok(value),
+        };
+        if chain.len() >= self.max_depth {
+            return Err(ErrorKind::DepthExceeded {
+                max_depth: self.max_depth,
+                chain: chain_to(name),
+            });
+        }
+
+        let chain: Vec<&str> = chain.iter().copied().chain([name]).collect();
+        let settings = Settings {
+            fail_fast: true,
+            ..self.settings
+        };
+        Template::parse_from(self.syntax, &value, &mut Position::default())
+            .render_with(|var| self.resolve(resolver, var, &chain), settings)
+            .map(|rendered| Some(Cow::Owned(rendered)))
+            .map_err(|errors| {
+                let err = errors.into_first();
+                match err.kind() {
+                    ErrorKind::Cycle(chain) => ErrorKind::Cycle(chain.clone()),
+                    ErrorKind::DepthExceeded { max_depth, chain } => ErrorKind::DepthExceeded {
+                        max_depth: *max_depth,
+                        chain: chain.clone(),
+                    },
+                    // Errors of other placeholders in the value, with their
+                    // position in the value
+                    _ => ErrorKind::Resolver(Box::new(err)),
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recursive_expansion() {
+        let values = [
+            ("a", "[%{{b}}|%{{b | upper}}]"),
+            ("b", "%{{c:-x}}%{{d}}"),
+            ("d", "d"),
+            ("escaped", "%%{{a}}"),
+            ("self", "%{{self}}"),
+            ("required", "%{{c:?c is needed}}"),
+        ];
+        let cases = [
+            ("%{{a}}", "[xd|XD]"),
+            ("%{{a:-z}} %{{c:-%{{d}}", "[xd|XD] %{{d"),
+            ("%{{escaped}}", "%{{a}}"),
+        ];
+        for (template, expected) in cases {
+            let rendered = replace_variables_recursive(template, &values, 2).unwrap();
+            assert_eq!(rendered, expected);
+        }
+        assert_eq!(
+            replace_variables_recursive("%{{b}}", &values, 0)
+                .unwrap_err()
+                .to_string(),
+            "1:1: %{{b}} expands deeper than 0 levels: b"
+        );
+
+        let err = replace_variables_recursive("x %{{self}}", &values, 2).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::Cycle(chain) if chain == &["self", "self"]));
+        assert_eq!(err.column(), 3);
+        let err = replace_variables_recursive("%{{a}}", &values, 1).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::DepthExceeded { max_depth: 1, chain } if chain == &["a", "b"]
+        ));
+        let err = replace_variables_recursive("%{{required}}", &values, 2).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "1:1: failed to resolve %{{required}}: 1:1: %{{c}}: c is needed"
+        );
+    }
+}
@@ -0,0 +1,438 @@
+#[cfg(any(feature = "serde_json", feature = "yaml"))]
+use crate::tree::{self, Node};
+use std::borrow::{Borrow, Cow};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+
+/// Provides the values of %{{variable}} placeholders.
+///
+/// Implemented for:
+/// - closures and functions `Fn(&str) -> T` where `T` is `String` (every
+///   variable is set) or `Option<String>` (`None` for unset variables)
+/// - `HashMap` and `BTreeMap` with string keys and values
+/// - slices and arrays of `(key, value)` pairs
+/// - [`Env`], the process environment
+/// - [`Layered`], a chain of other resolvers
+/// - [`Tree`](crate::Tree), nested maps, lists and scalars
+/// - `serde_json::Map` and `serde_json::Value`, with the `serde_json` feature
+/// - `yaml_rust2::Yaml`, with the `yaml` feature
+///
+/// The nested resolvers follow dotted and indexed paths such as
+/// `%{{host_config.mounts[0].source}}`.
+///
+/// Example usage:
+/// ```
+/// use std::collections::HashMap;
+/// use str_var_subst::replace_variables;
+/// let values = HashMap::from([(String::from("name"), String::from("John"))]);
+/// let parsed_str = replace_variables("Hi my name is %{{name}}!", &values).unwrap();
+/// assert_eq!(parsed_str, "Hi my name is John!");
+///
+/// let parsed_str = replace_variables("Hi my name is %{{name}}!", &[("name", "Jane")]).unwrap();
+/// assert_eq!(parsed_str, "Hi my name is Jane!");
+/// ```
+///
+/// Closures need an explicit parameter type to be accepted as a resolver:
+/// ```
+/// use str_var_subst::replace_variables;
+/// let parsed_str = replace_variables("%{{a}}%{{b:-2}}", &|var: &str| match var {
+///     "a" => Some(String::from("1")),
+///     _ => None,
+/// })
+/// .unwrap();
+/// assert_eq!(parsed_str, "12");
+/// ```
+///
+pub trait Resolver {
+    /// Returns the value of the variable, or `None` if it is unset
+    fn resolve(&self, name: &str) -> Option<Cow<'_, str>>;
+
+    /// Describes where the value of the variable comes from, if the resolver
+    /// combines several sources. Used for reporting, e.g. by [`check`](crate::check).
+    fn source_of(&self, _name: &str) -> Option<&str> {
+        None
+    }
+
+    /// Returns the number of items of a list-valued variable, or `None` if it
+    /// is not a list. Used by `%{{#each variable}}` sections, which refer to
+    /// the items as `variable[0]`, `variable[1]` and so on.
+    ///
+    /// By default there are no lists, as a resolver that has a value for
+    /// every name would have endless ones. The maps, slices and [`Env`] count
+    /// the variables `variable[0]`, `variable[1]`, ... up to the first unset
+    /// one, the nested resolvers return the length of their lists.
+    fn list_len(&self, _name: &str) -> Option<usize> {
+        None
+    }
+
+    /// Returns the value of the variable as JSON, for placeholders that make
+    /// up a whole JSON string, see [`replace_variables_value`](crate::replace_variables_value).
+    /// By default the value is a JSON string.
+    #[cfg(feature = "serde_json")]
+    fn resolve_value(&self, name: &str) -> Option<serde_json::Value> {
+        self.resolve(name)
+            .map(|value| serde_json::Value::String(value.into_owned()))
+    }
+}
+
+/// Number of the variables `name[0]`, `name[1]`, ... up to the first unset
+/// one, for resolvers with a finite number of variables
+fn indexed_len<R>(resolver: &R, name: &str) -> Option<usize>
+where
+    R: Resolver + ?Sized,
+{
+    let len = (0..)
+        .take_while(|index| resolver.resolve(&format!("{}[{}]", name, index)).is_some())
+        .count();
+    (len > 0).then_some(len)
+}
+
+/// Resolves variables from the environment of the current process.
+/// Variables that are unset or whose value is not valid unicode are unset.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Env;
+
+impl Resolver for Env {
+    fn resolve(&self, name: &str) -> Option<Cow<'_, str>> {
+        env::var(name).ok().map(Cow::Owned)
+    }
+
+    fn list_len(&self, name: &str) -> Option<usize> {
+        indexed_len(self, name)
+    }
+}
+
+impl<F, T> Resolver for F
+where
+    F: Fn(&str) -> T,
+    T: Into<Option<String>>,
+{
+    fn resolve(&self, name: &str) -> Option<Cow<'_, str>> {
+        self(name).into().map(Cow::Owned)
+    }
+}
+
+impl<K, V, S> Resolver for HashMap<K, V, S>
+where
+    K: Borrow<str> + Hash + Eq,
+    V: AsRef<str>,
+    S: BuildHasher,
+{
+    fn resolve(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.get(name).map(|value| Cow::Borrowed(value.as_ref()))
+    }
+
+    fn list_len(&self, name: &str) -> Option<usize> {
+        indexed_len(self, name)
+    }
+}
+
+impl<K, V> Resolver for BTreeMap<K, V>
+where
+    K: Borrow<str> + Ord,
+    V: AsRef<str>,
+{
+    fn resolve(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.get(name).map(|value| Cow::Borrowed(value.as_ref()))
+    }
+
+    fn list_len(&self, name: &str) -> Option<usize> {
+        indexed_len(self, name)
+    }
+}
+
+/// The first pair with a matching key wins
+impl<K, V> Resolver for [(K, V)]
+where
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    fn resolve(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.iter()
+            .find(|(key, _)| key.as_ref() == name)
+            .map(|(_, value)| Cow::Borrowed(value.as_ref()))
+    }
+
+    fn list_len(&self, name: &str) -> Option<usize> {
+        indexed_len(self, name)
+    }
+}
+
+impl<K, V, const N: usize> Resolver for [(K, V); N]
+where
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    fn resolve(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.as_slice().resolve(name)
+    }
+
+    fn list_len(&self, name: &str) -> Option<usize> {
+        self.as_slice().list_len(name)
+    }
+}
+
+/// Strings are used as they are, other JSON values are written as JSON text,
+/// e.g. `2`, `false` or `null`. Typed values are kept by
+/// [`replace_variables_value`](crate::replace_variables_value).
+#[cfg(feature = "serde_json")]
+impl Resolver for serde_json::Map<String, serde_json::Value> {
+    fn resolve(&self, name: &str) -> Option<Cow<'_, str>> {
+        tree::walk(name, |key| self.get(key)).map(json_text)
+    }
+
+    fn resolve_value(&self, name: &str) -> Option<serde_json::Value> {
+        tree::walk(name, |key| self.get(key)).cloned()
+    }
+
+    fn list_len(&self, name: &str) -> Option<usize> {
+        Some(tree::walk(name, |key| self.get(key))?.as_array()?.len())
+    }
+}
+
+/// Same as `serde_json::Map` for objects, other values have no variables
+#[cfg(feature = "serde_json")]
+impl Resolver for serde_json::Value {
+    fn resolve(&self, name: &str) -> Option<Cow<'_, str>> {
+        tree::walk(name, |key| self.key(key)).map(json_text)
+    }
+
+    fn resolve_value(&self, name: &str) -> Option<serde_json::Value> {
+        tree::walk(name, |key| self.key(key)).cloned()
+    }
+
+    fn list_len(&self, name: &str) -> Option<usize> {
+        Some(tree::walk(name, |key| self.key(key))?.as_array()?.len())
+    }
+}
+
+#[cfg(feature = "serde_json")]
+fn json_text(value: &serde_json::Value) -> Cow<'_, str> {
+    match value {
+        serde_json::Value::String(value) => Cow::Borrowed(value.as_str()),
+        value => Cow::Owned(value.to_string()),
+    }
+}
+
+/// Strings, numbers and booleans are used as they are written in the YAML
+/// document, `null`, sequences and mappings are unset
+#[cfg(feature = "yaml")]
+impl Resolver for yaml_rust2::Yaml {
+    fn resolve(&self, name: &str) -> Option<Cow<'_, str>> {
+        use yaml_rust2::Yaml;
+        match tree::walk(name, |key| self.key(key))? {
+            Yaml::String(value) | Yaml::Real(value) => Some(Cow::Borrowed(value)),
+            Yaml::Integer(value) => Some(Cow::Owned(value.to_string())),
+            Yaml::Boolean(value) => Some(Cow::Owned(value.to_string())),
+            _ => None,
+        }
+    }
+
+    fn list_len(&self, name: &str) -> Option<usize> {
+        Some(tree::walk(name, |key| self.key(key))?.as_vec()?.len())
+    }
+}
+
+/// Resolves variables from a list of named layers, tried in the order they
+/// were added. The first layer that has a value for a variable wins.
+///
+/// Example usage:
+/// ```
+/// use std::collections::HashMap;
+/// use str_var_subst::{replace_variables, Env, Layered};
+/// let overrides = HashMap::from([("log_level", "debug")]);
+/// let defaults = [("log_level", "info"), ("max_files", "2")];
+/// let resolver = Layered::new()
+///     .layer("cli", overrides)
+///     .layer("env", Env)
+///     .layer("defaults", defaults);
+///
+/// let rendered = replace_variables("%{{log_level}}/%{{max_files}}", &resolver).unwrap();
+/// assert_eq!(rendered, "debug/2");
+/// assert_eq!(resolver.source_of("log_level"), Some("cli"));
+/// assert_eq!(resolver.source_of("max_files"), Some("defaults"));
+/// ```
+///
+#[derive(Default)]
+pub struct Layered<'a> {
+    layers: Vec<(String, Box<dyn Resolver + 'a>)>,
+}
+
+impl<'a> Layered<'a> {
+    /// Creates a resolver without any layers, every variable is unset
+    pub fn new() -> Self {
+        Layered { layers: Vec::new() }
+    }
+
+    /// Adds a layer with a lower precedence than all the existing ones
+    pub fn layer<R>(mut self, name: impl Into<String>, resolver: R) -> Self
+    where
+        R: Resolver + 'a,
+    {
+        self.layers.push((name.into(), Box::new(resolver)));
+        self
+    }
+
+    /// Resolves the variable and returns the name of the layer that supplied it
+    pub fn resolve_with_source(&self, name: &str) -> Option<(Cow<'_, str>, &str)> {
+        self.layers.iter().find_map(|(layer, resolver)| {
+            resolver.resolve(name).map(|value| (value, layer.as_str()))
+        })
+    }
+
+    /// Name of the layer that supplies the variable, or `None` if it is unset
+    pub fn source_of(&self, name: &str) -> Option<&str> {
+        self.resolve_with_source(name).map(|(_, layer)| layer)
+    }
+
+    /// Names of the layers, from the highest to the lowest precedence
+    pub fn layer_names(&self) -> impl Iterator<Item = &str> {
+        self.layers.iter().map(|(layer, _)| layer.as_str())
+    }
+}
+
+impl fmt::Debug for Layered<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Layered")
+            .field("layers", &self.layer_names().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Resolver for Layered<'_> {
+    fn resolve(&self, name: &str) -> Option<Cow<'_, str>> {
+        self.resolve_with_source(name).map(|(value, _)| value)
+    }
+
+    fn source_of(&self, name: &str) -> Option<&str> {
+        Layered::source_of(self, name)
+    }
+
+    fn list_len(&self, name: &str) -> Option<usize> {
+        self.layers
+            .iter()
+            .find_map(|(_, resolver)| resolver.list_len(name))
+    }
+
+    #[cfg(feature = "serde_json")]
+    fn resolve_value(&self, name: &str) -> Option<serde_json::Value> {
+        self.layers
+            .iter()
+            .find_map(|(_, resolver)| resolver.resolve_value(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve_all<R: Resolver + ?Sized>(resolver: &R) -> Vec<Option<String>> {
+        ["a", "b", "c"]
+            .iter()
+            .map(|name| resolver.resolve(name).map(Cow::into_owned))
+            .collect()
+    }
+
+    #[test]
+    fn test_builtin_resolvers() {
+        let expected = [Some(String::from("1")), Some(String::new()), None];
+        let pairs = [("a", "1"), ("b", ""), ("a", "shadowed")];
+
+        let hash_map: HashMap<String, String> = pairs[..2]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let btree_map: BTreeMap<&str, &str> = pairs[..2].iter().copied().collect();
+        let closure = |var: &str| match var {
+            "a" => Some(String::from("1")),
+            "b" => Some(String::new()),
+            _ => None,
+        };
+
+        assert_eq!(resolve_all(&pairs), expected);
+        assert_eq!(resolve_all(&pairs[..]), expected);
+        assert_eq!(resolve_all(&hash_map), expected);
+        assert_eq!(resolve_all(&btree_map), expected);
+        assert_eq!(resolve_all(&closure), expected);
+        assert_eq!(
+            resolve_all(&|var: &str| var.to_uppercase()),
+            [
+                Some(String::from("A")),
+                Some(String::from("B")),
+                Some(String::from("C"))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_env_resolver() {
+        let key = "STR_VAR_SUBST_TEST_ENV_RESOLVER";
+        env::set_var(key, "value");
+        let value = Env.resolve(key).map(Cow::into_owned);
+        env::remove_var(key);
+        assert_eq!(value.as_deref(), Some("value"));
+        assert_eq!(Env.resolve(key), None);
+    }
+
+    #[test]
+    fn test_layered_precedence() {
+        let cli = HashMap::from([("a", "cli")]);
+        let vars_file = BTreeMap::from([("a", "file"), ("b", "file")]);
+        let defaults = [("a", "default"), ("b", "default"), ("c", "default")];
+        let resolver = Layered::new()
+            .layer("cli", cli)
+            .layer("vars file", vars_file)
+            .layer("defaults", defaults);
+
+        let resolved: Vec<_> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|name| {
+                resolver
+                    .resolve_with_source(name)
+                    .map(|(value, layer)| format!("{} from {}", value, layer))
+            })
+            .collect();
+        assert_eq!(
+            resolved,
+            [
+                Some(String::from("cli from cli")),
+                Some(String::from("file from vars file")),
+                Some(String::from("default from defaults")),
+                None
+            ]
+        );
+        assert_eq!(resolver.source_of("d"), None);
+        assert_eq!(
+            format!("{:?}", resolver),
+            r#"Layered { layers: ["cli", "vars file", "defaults"] }"#
+        );
+        assert_eq!(Layered::new().resolve("a"), None);
+    }
+
+    #[test]
+    fn test_list_len() {
+        let pairs = [("l[0]", "a"), ("l[1]", "b"), ("m", "c")];
+        let btree_map: BTreeMap<&str, &str> = pairs.iter().copied().collect();
+        assert_eq!(pairs.list_len("l"), Some(2));
+        assert_eq!(btree_map.list_len("l"), Some(2));
+        assert_eq!(btree_map.list_len("m"), None);
+        assert_eq!(Layered::new().layer("pairs", pairs).list_len("l"), Some(2));
+
+        /// Has a value for every name, like a catch-all fallback
+        struct Fallback;
+        impl Resolver for Fallback {
+            fn resolve(&self, _name: &str) -> Option<Cow<'_, str>> {
+                Some(Cow::Borrowed("x"))
+            }
+        }
+        assert_eq!(Fallback.list_len("l"), None);
+        let template = "%{{#each l}}.%{{else}}none%{{/each}}";
+        assert_eq!(
+            crate::replace_variables(template, &Fallback).unwrap(),
+            "none"
+        );
+        assert!(!crate::check(template, &Fallback).would_fail(true));
+    }
+}
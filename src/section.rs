@@ -0,0 +1,351 @@
+use crate::template::Template;
+use lazy_static::lazy_static;
+use std::ops::Range;
+
+lazy_static! {
+    pub(crate) static ref DEFAULT_TRUTHINESS: Truthiness = Truthiness::default();
+}
+
+/// Decides which variables are true for `%{{#if variable}}` and
+/// `%{{#unless variable}}` sections. Unset variables are always false.
+///
+/// By default the values `""`, `"0"` and `"false"` (in any case) are false
+/// too and every other value is true. Configured with
+/// [`SubstituterBuilder::truthiness`](crate::SubstituterBuilder::truthiness).
+///
+/// Example usage:
+/// ```
+/// use str_var_subst::Truthiness;
+/// let truthiness = Truthiness::default();
+/// assert!(truthiness.is_true(Some("yes")));
+/// assert!(!truthiness.is_true(Some("FALSE")));
+/// assert!(!truthiness.is_true(None));
+///
+/// let truthiness = Truthiness::with_falsy(["", "no", "off"]);
+/// assert!(truthiness.is_true(Some("0")));
+/// assert!(!truthiness.is_true(Some("off")));
+/// assert!(truthiness.is_true(Some("OFF")));
+/// assert!(!truthiness.ignore_case(true).is_true(Some("OFF")));
+///
+/// assert!(Truthiness::unset_only().is_true(Some("")));
+/// ```
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Truthiness {
+    falsy: Vec<String>,
+    ignore_case: bool,
+}
+
+impl Default for Truthiness {
+    /// Unset variables and `""`, `"0"` and `"false"`, in any case, are false
+    fn default() -> Self {
+        Truthiness::with_falsy(["", "0", "false"]).ignore_case(true)
+    }
+}
+
+impl Truthiness {
+    /// Unset variables and exactly these values are false
+    pub fn with_falsy<I, S>(falsy: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Truthiness {
+            falsy: falsy.into_iter().map(Into::into).collect(),
+            ignore_case: false,
+        }
+    }
+
+    /// Only unset variables are false, even an empty value is true
+    pub fn unset_only() -> Self {
+        Truthiness::with_falsy(Vec::<String>::new())
+    }
+
+    /// Compares values with the false ones ignoring ASCII case
+    pub fn ignore_case(mut self, ignore_case: bool) -> Self {
+        self.ignore_case = ignore_case;
+        self
+    }
+
+    /// Whether a variable with this value is true, `None` if it is unset
+    pub fn is_true(&self, value: Option<&str>) -> bool {
+        match value {
+            Some(value) => !self.falsy.iter().any(|falsy| match self.ignore_case {
+                true => falsy.eq_ignore_ascii_case(value),
+                false => falsy == value,
+            }),
+            None => false,
+        }
+    }
+}
+
+/// A `%{{#if variable}}...%{{else}}...%{{/if}}` section of a template, or
+/// one of the other kinds of sections
+#[derive(Debug, Clone)]
+pub(crate) struct Section {
+    pub(crate) kind: SectionKind,
+    /// Name of the variable the section depends on
+    pub(crate) condition: String,
+    /// The opening tag as written, e.g. `%{{#if variable}}`
+    pub(crate) tag: String,
+    /// Rendered if the condition holds, or once per item
+    pub(crate) body: Template,
+    /// Rendered otherwise, the part after `%{{else}}`
+    pub(crate) otherwise: Template,
+    /// Byte range from the opening to the closing tag
+    pub(crate) span: Range<usize>,
+    pub(crate) position: (usize, usize),
+}
+
+impl Section {
+    /// Whether the body of an `#if`, `#unless` or `#sep` section is rendered
+    /// rather than the part after `%{{else}}`, given the value of the
+    /// condition. An unset variable is true if it is a non-empty list.
+    pub(crate) fn holds<F>(&self, value: Option<&str>, truthiness: &Truthiness, list_len: F) -> bool
+    where
+        F: FnOnce() -> usize,
+    {
+        let condition = match (self.kind, value) {
+            // `@last` is set for the last item only
+            (SectionKind::Separator, value) => return value.is_none(),
+            (_, Some(value)) => truthiness.is_true(Some(value)),
+            (_, None) => list_len() > 0,
+        };
+        condition == (self.kind == SectionKind::If)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SectionKind {
+    /// `#if`: the body is rendered if the variable is true
+    If,
+    /// `#unless`: the body is rendered if the variable is false
+    Unless,
+    /// `#each`: the body is rendered once per item of a list-valued variable,
+    /// the `%{{else}}` part if the list is empty
+    Each,
+    /// `#sep`: the body is rendered for every item of the enclosing `#each`
+    /// section but the last one
+    Separator,
+}
+
+impl SectionKind {
+    pub(crate) fn from_keyword(keyword: &str) -> SectionKind {
+        match keyword {
+            "if" => SectionKind::If,
+            "unless" => SectionKind::Unless,
+            "each" => SectionKind::Each,
+            _ => SectionKind::Separator,
+        }
+    }
+
+    pub(crate) fn keyword(self) -> &'static str {
+        match self {
+            SectionKind::If => "if",
+            SectionKind::Unless => "unless",
+            SectionKind::Each => "each",
+            SectionKind::Separator => "sep",
+        }
+    }
+}
+
+/// The item of an `%{{#each list}}` section that is being rendered
+pub(crate) struct Loop {
+    /// Path of the list, e.g. `mounts` or `services[0].ports`
+    pub(crate) list: String,
+    pub(crate) index: usize,
+    pub(crate) len: usize,
+}
+
+/// What a `%{{@item}}`, `%{{@index}}`, `%{{@first}}` or `%{{@last}}`
+/// placeholder refers to
+pub(crate) enum LoopVariable {
+    /// The value of the variable, `None` if it is unset
+    Value(Option<String>),
+    /// The path of the item, or of a part of it, to resolve
+    Path(String),
+}
+
+impl Loop {
+    /// The loop variable named `name`, `None` if the name does not start
+    /// with `@`. `@first` and `@last` are `true` for the first and last
+    /// item and unset otherwise.
+    pub(crate) fn variable(&self, name: &str) -> Option<LoopVariable> {
+        let flag = |set: bool| LoopVariable::Value(set.then(|| String::from("true")));
+        Some(match name.strip_prefix('@')? {
+            "index" => LoopVariable::Value(Some(self.index.to_string())),
+            "first" => flag(self.index == 0),
+            "last" => flag(self.index + 1 == self.len),
+            name => match name.strip_prefix("item") {
+                Some(rest) if rest.is_empty() || rest.starts_with(['.', '[']) => {
+                    LoopVariable::Path(format!("{}[{}]{}", self.list, self.index, rest))
+                }
+                _ => LoopVariable::Value(None),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::template::Template;
+    use crate::{replace_variables, Substituter, Tree, Truthiness};
+
+    #[test]
+    fn test_sections() {
+        let values = [
+            ("devices", "/dev/ttyUSB0"),
+            ("zero", "0"),
+            ("no", "False"),
+            ("empty", ""),
+            ("yes", "true"),
+        ];
+        let cases = [
+            (
+                "a%{{#if devices}}[%{{devices}}]%{{/if}}b",
+                "a[/dev/ttyUSB0]b",
+            ),
+            ("a%{{#if unset}}[%{{devices}}]%{{/if}}b", "ab"),
+            ("%{{#if zero}}x%{{else}}y%{{/if}}", "y"),
+            ("%{{#if no}}x%{{else}}y%{{/if}}", "y"),
+            ("%{{#if empty}}x%{{else}}y%{{/if}}", "y"),
+            ("%{{#unless yes}}x%{{else}}y%{{/unless}}", "y"),
+            ("%{{#unless unset}}x%{{/unless}}", "x"),
+            (
+                "%{{#if yes}}1%{{#unless zero}}2%{{#if no}}3%{{else}}4%{{/if}}%{{/unless}}%{{/if}}",
+                "124",
+            ),
+            // unbalanced tags are literal text
+            ("%{{#if yes}}x", "%{{#if yes}}x"),
+            ("%{{#if yes}}x%{{else}}y", "%{{#if yes}}x%{{else}}y"),
+            ("x%{{/if}} %{{else}}", "x%{{/if}} %{{else}}"),
+            ("%{{#if yes}}x%{{/unless}}", "%{{#if yes}}x%{{/unless}}"),
+            (
+                "%{{#if yes}}%{{#if zero}}x%{{/if}}%{{devices}}",
+                "%{{#if yes}}/dev/ttyUSB0",
+            ),
+            ("%{{#if yes}}a%{{else}}b%{{else}}c%{{/if}}", "a"),
+            ("%%{{#if yes}}x%%{{/if}}", "%{{#if yes}}x%{{/if}}"),
+            (
+                "%{{#if}}%{{#if 1a}}%{{#with yes}}",
+                "%{{#if}}%{{#if 1a}}%{{#with yes}}",
+            ),
+        ];
+        for (template, expected) in cases {
+            let rendered = replace_variables(template, &values).unwrap();
+            assert_eq!(rendered, expected, "rendering {:?}", template);
+        }
+
+        let template = Template::parse(
+            "%{{a}}\n%{{#if b}}%{{c}}%{{else}}%{{d:?d is needed}}%{{/if}}%{{e}}%{{else}}",
+        );
+        let names: Vec<_> = template.variables().into_iter().map(|v| v.name).collect();
+        assert_eq!(names, ["a", "c", "d", "e"]);
+        assert_eq!(template.render(&[("b", "1")]).unwrap(), "\n%{{else}}");
+        let err = template.render(&[("b", "0")]).unwrap_err();
+        assert_eq!(err.to_string(), "2:26: %{{d}}: d is needed");
+        let errors = template.render_strict(&[("b", "1")]).unwrap_err();
+        assert_eq!(errors.len(), 3);
+    }
+
+    #[test]
+    fn test_each() {
+        let values = Tree::from_iter([
+            (
+                "mounts",
+                Tree::from(vec![
+                    Tree::from_iter([("source", "/a"), ("destination", "/x")]),
+                    Tree::from_iter([("source", "/b"), ("destination", "/y")]),
+                ]),
+            ),
+            (
+                "services",
+                Tree::from(vec![
+                    Tree::from_iter([
+                        ("name", Tree::from("a")),
+                        ("ports", Tree::from(vec!["80", "443"])),
+                    ]),
+                    Tree::from_iter([
+                        ("name", Tree::from("b")),
+                        ("ports", Tree::from(Vec::<Tree>::new())),
+                    ]),
+                ]),
+            ),
+            ("ports", Tree::from(vec!["80"])),
+            ("empty", Tree::from(Vec::<Tree>::new())),
+            ("name", Tree::from("x")),
+        ]);
+        let cases = [
+            (
+                "[%{{#each mounts}}%{{@item.source}}:%{{@item.destination}}%{{#sep}},%{{/sep}}%{{/each}}]",
+                "[/a:/x,/b:/y]",
+            ),
+            (
+                "%{{#each mounts}}%{{@index}}%{{@first:-}}%{{@last:-}}%{{#if @first}}F%{{/if}} %{{/each}}",
+                "0trueF 1true ",
+            ),
+            (
+                "%{{#each services}}%{{@item.name}}(%{{#each @item.ports}}%{{@item}}%{{#sep}} %{{/sep}}%{{else}}none%{{/each}}) %{{/each}}",
+                "a(80 443) b(none) ",
+            ),
+            ("%{{#each empty}}x%{{else}}empty%{{/each}}", "empty"),
+            ("%{{#each unset}}x%{{/each}}%{{#each name}}y%{{/each}}", ""),
+            ("%{{#if ports}}p%{{/if}}%{{#if empty}}e%{{/if}}", "p"),
+            ("%{{#each ports}}%{{@item[0]}}%{{@other}}%{{@items}}%{{/each}}", ""),
+            ("%{{@index}}%{{#sep}}always%{{/sep}}", "always"),
+            ("%{{#each ports}}%{{#each ports}}x%{{/each}}%{{/each}}", "x"),
+        ];
+        for (template, expected) in cases {
+            let rendered = replace_variables(template, &values).unwrap();
+            assert_eq!(rendered, expected, "rendering {:?}", template);
+        }
+
+        // the items of flat resolvers are `list[0]`, `list[1]`, ...
+        let values = [
+            ("ports[0]", "80"),
+            ("ports[1]", "443"),
+            ("ports[3]", "8080"),
+        ];
+        let template = Template::parse("%{{#each ports}}%{{@item}}:%{{@item | upper}}\n%{{/each}}");
+        assert_eq!(template.render(&values).unwrap(), "80:80\n443:443\n");
+        assert_eq!(template.render(&|_: &str| String::from("1")).unwrap(), "");
+
+        let template = Template::parse(
+            "%{{#each ports}}\n%{{@item:?}}%{{@item.port:?port is needed}}%{{/each}}",
+        );
+        let err = template.render(&values).unwrap_err();
+        assert_eq!(err.to_string(), "2:13: %{{@item.port}}: port is needed");
+        let errors = template
+            .render_strict(&[("ports[0]", "80"), ("ports[1]", "443")])
+            .unwrap_err();
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn test_truthiness() {
+        let values = [("flag", "no"), ("empty", "")];
+        let template = "%{{#if flag}}on%{{else}}off%{{/if}} %{{#if empty}}set%{{/if}}";
+        assert_eq!(replace_variables(template, &values).unwrap(), "on ");
+
+        let substituter = Substituter::builder()
+            .truthiness(Truthiness::with_falsy(["NO"]).ignore_case(true))
+            .build()
+            .unwrap();
+        assert_eq!(substituter.replace(template, &values).unwrap(), "off set");
+
+        let mustache = Substituter::builder()
+            .open("{{")
+            .close("}}")
+            .whitespace(true)
+            .truthiness(Truthiness::unset_only())
+            .build()
+            .unwrap();
+        let rendered = mustache
+            .replace(
+                "{{ #if empty }}[{{ empty }}]{{ else }}-{{ /if }}{{#unless unset}}!{{/unless}}",
+                &values,
+            )
+            .unwrap();
+        assert_eq!(rendered, "[]!");
+    }
+}
@@ -0,0 +1,421 @@
+use crate::error::StreamError;
+use crate::resolver::Resolver;
+use crate::section::SectionKind;
+use crate::template::{Position, Template, DEFAULT_SYNTAX};
+use lazy_static::lazy_static;
+use regex::{Match, Regex};
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::str;
+
+lazy_static! {
+    /// Matches the start of an escape, placeholder or section tag that is cut
+    /// off by the end of the text read so far
+    static ref PARTIAL_RE: Regex = Regex::new(
+        r"%(?:%\{?|\{(?:\{(?:[#/][\w\s.\[\]@]*\}?|!?(?:[\w.\[\]@]|%\{\{[^}]*\}\})*(?:%(?:\{(?:\{[^}]*\}?)?)?|\}|:|:?[-?](?s:.)*|\s+|\s*\|(?s:.)*)?)?)?)?\z"
+    )
+    .unwrap();
+    /// Matches the start of a placeholder whose name is cut off by the text
+    /// that follows, e.g. `%{{db_` before `%{{env}}`
+    static ref OPEN_NAME_RE: Regex = Regex::new(r"%\{\{!?(?:[\w.\[\]@]|%\{\{[^}]*\}\})*\z").unwrap();
+}
+
+const BUFFER_SIZE: usize = 8 * 1024;
+
+/// Escape for a literal `%{{` in the default syntax
+const ESCAPE: &str = "%%{{";
+
+/// Longest text held back for a placeholder that is cut off, e.g. the
+/// default value of `%{{a:-` that is never closed. Beyond it the text is
+/// written as literal text.
+const MAX_PLACEHOLDER_LEN: usize = 64 * 1024;
+
+/// Longest text held back for a section whose closing tag has not been read
+/// yet. Beyond it streaming fails.
+const MAX_SECTION_LEN: usize = 1024 * 1024;
+
+/// Same as [`replace_variables`](crate::replace_variables) but reads the
+/// template from `reader` and writes the result to `writer` as it goes.
+/// Only the text of a placeholder that is split between two reads is held
+/// back until the rest of it arrives, so the whole template is never held
+/// in memory. A `%{{#if variable}}` section is held back until its closing
+/// tag has been read, so the memory used grows with the size of the
+/// largest section.
+///
+/// A placeholder that is still not closed after 64 KiB of text is not one,
+/// e.g. a stray `%{{a:-` in the middle of a large file: it is written as
+/// literal text together with the text that follows it. A section that is
+/// not closed within 1 MiB cannot be streamed: it fails with an
+/// [`io::ErrorKind::InvalidData`] error, without writing any of the section.
+///
+/// The template must be valid UTF-8. Stops at the first error, in which case
+/// the text before the offending placeholder has already been written.
+///
+/// Example usage:
+/// ```
+/// use str_var_subst::replace_variables_stream;
+/// let template = "Hi my name is %{{name}}!";
+/// let mut output = Vec::new();
+/// replace_variables_stream(template.as_bytes(), &mut output, &[("name", "John")]).unwrap();
+/// assert_eq!(output, b"Hi my name is John!");
+/// ```
+///
+pub fn replace_variables_stream<I, O, R>(
+    mut reader: I,
+    mut writer: O,
+    resolver: &R,
+) -> Result<(), StreamError>
+where
+    I: Read,
+    O: Write,
+    R: Resolver + ?Sized,
+{
+    let mut buffer = [0; BUFFER_SIZE];
+    let mut undecoded = Vec::new();
+    let mut pending = String::new();
+    let mut position = Position::default();
+    let mut scan = Scan::default();
+
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let held_len = pending.len();
+        undecoded.extend_from_slice(&buffer[..read]);
+        let valid_len = match str::from_utf8(&undecoded) {
+            Ok(text) => text.len(),
+            // a multi-byte character split between two reads
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e).into()),
+        };
+        pending.push_str(str::from_utf8(&undecoded[..valid_len]).unwrap());
+        undecoded.drain(..valid_len);
+
+        // Text held back for a long placeholder or section can only be
+        // completed by a closing `}}`, until then there is no need to scan it
+        // again
+        let closed = pending.as_bytes()[held_len.saturating_sub(1)..]
+            .windows(2)
+            .any(|pair| pair == b"}}");
+        let too_long = [MAX_PLACEHOLDER_LEN, MAX_SECTION_LEN]
+            .iter()
+            .any(|&max_len| held_len <= max_len && pending.len() > max_len);
+        if held_len >= BUFFER_SIZE && !closed && !too_long {
+            continue;
+        }
+
+        let complete_len = match scan.complete_prefix_len(&pending) {
+            Ok(complete_len) => complete_len,
+            Err(section_start) => {
+                render_piece(
+                    &pending[..section_start],
+                    &mut position,
+                    &mut writer,
+                    resolver,
+                )?;
+                let tag = DEFAULT_SYNTAX.re.find(&pending[section_start..]).unwrap();
+                let (line, column) = position.line_column();
+                let message = format!(
+                    "{}:{}: section {} is not closed within {} bytes",
+                    line,
+                    column,
+                    tag.as_str(),
+                    MAX_SECTION_LEN
+                );
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message).into());
+            }
+        };
+        render_piece(
+            &pending[..complete_len],
+            &mut position,
+            &mut writer,
+            resolver,
+        )?;
+        pending.drain(..complete_len);
+        scan.consume(complete_len);
+    }
+
+    if !undecoded.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "stream did not end with a complete UTF-8 character",
+        )
+        .into());
+    }
+    // whatever is left is an unterminated placeholder, i.e. literal text
+    render_piece(&pending, &mut position, &mut writer, resolver)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// What is known about the text held back between two reads, so the text
+/// is scanned only once
+#[derive(Default)]
+struct Scan {
+    /// Length of the prefix of the text scanned so far, which does not end
+    /// with a placeholder that is cut off
+    scanned_len: usize,
+    /// Start and kind of the sections of the scanned text whose closing tag
+    /// has not been read yet, outermost first
+    open_sections: Vec<(usize, SectionKind)>,
+}
+
+impl Scan {
+    /// Length of the longest prefix of the text that can be rendered without
+    /// knowing what comes after it. Only the text appended since the last
+    /// call is scanned. Fails with the start of the outermost open section
+    /// if it is longer than `MAX_SECTION_LEN`.
+    fn complete_prefix_len(&mut self, text: &str) -> Result<usize, usize> {
+        let start = self.scanned_len;
+        let len = match start + complete_placeholders_len(&text[start..]) {
+            len if text.len() - len > MAX_PLACEHOLDER_LEN => text.len(),
+            len => len,
+        };
+        for caps in DEFAULT_SYNTAX.re.captures_iter(&text[start..len]) {
+            if let Some(keyword) = caps.name("section").or_else(|| caps.name("separator")) {
+                let kind = SectionKind::from_keyword(keyword.as_str());
+                self.open_sections
+                    .push((start + caps.get(0).unwrap().start(), kind));
+            } else if let Some(keyword) = caps.name("end") {
+                if self
+                    .open_sections
+                    .last()
+                    .is_some_and(|(_, kind)| kind.keyword() == keyword.as_str())
+                {
+                    self.open_sections.pop();
+                }
+            }
+        }
+        self.scanned_len = len;
+        match self.open_sections.first() {
+            Some(&(section_start, _)) if len - section_start > MAX_SECTION_LEN => {
+                Err(section_start)
+            }
+            Some(&(section_start, _)) => Ok(section_start),
+            None => Ok(len),
+        }
+    }
+
+    /// Forgets the first `len` bytes of the text once they are rendered
+    fn consume(&mut self, len: usize) {
+        self.scanned_len -= len;
+        for (start, _) in &mut self.open_sections {
+            *start -= len;
+        }
+    }
+}
+
+/// Length of the longest prefix of the text that does not end with a
+/// placeholder that is cut off
+fn complete_placeholders_len(text: &str) -> usize {
+    // `%%{{` escapes do not end the text that may be cut off, they can be
+    // part of the default value of a placeholder, e.g. `%{{a:-%%{{`
+    let mut escapes = Vec::new();
+    let mut last_match = None;
+    for m in DEFAULT_SYNTAX.re.find_iter(text) {
+        match m.as_str() {
+            ESCAPE => escapes.push(m.range()),
+            _ => last_match = Some(m),
+        }
+    }
+    // the last placeholder may be nested in the name of a placeholder that
+    // is cut off, e.g. `%{{env}}` in `%{{db_%{{env}}_url`
+    if let Some(open) =
+        last_match.and_then(|m| find_outside(&OPEN_NAME_RE, &text[..m.start()], 0, &escapes))
+    {
+        if PARTIAL_RE
+            .find(&text[open.start()..])
+            .is_some_and(|partial| partial.start() == 0)
+        {
+            return open.start();
+        }
+    }
+    let last_match_end = last_match.map_or(0, |m| m.end());
+    match find_outside(&PARTIAL_RE, text, last_match_end, &escapes) {
+        Some(partial) => partial.start(),
+        None => text.len(),
+    }
+}
+
+/// First match of the regular expression from `start` on that does not
+/// start inside one of the escapes, so the text is never cut inside an
+/// escape, e.g. before the `%{{` of `%%{{`
+fn find_outside<'t>(
+    re: &Regex,
+    haystack: &'t str,
+    mut start: usize,
+    escapes: &[Range<usize>],
+) -> Option<Match<'t>> {
+    loop {
+        let m = re.find_at(haystack, start)?;
+        let index = escapes.partition_point(|escape| escape.end <= m.start());
+        match escapes.get(index) {
+            Some(escape) if escape.start < m.start() => start = escape.end,
+            _ => return Some(m),
+        }
+    }
+}
+
+fn render_piece<O, R>(
+    text: &str,
+    position: &mut Position,
+    writer: &mut O,
+    resolver: &R,
+) -> Result<(), StreamError>
+where
+    O: Write,
+    R: Resolver + ?Sized,
+{
+    if text.is_empty() {
+        return Ok(());
+    }
+    let rendered = Template::parse_from(&DEFAULT_SYNTAX, text, position).render(resolver)?;
+    writer.write_all(rendered.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replace_variables;
+
+    /// Hands out the data one byte per read
+    struct OneByteReader<'a>(&'a [u8]);
+
+    impl Read for OneByteReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.split_first() {
+                Some((first, rest)) if !buf.is_empty() => {
+                    buf[0] = *first;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    const VALUES: [(&str, &str); 5] = [
+        ("test_num", "1"),
+        ("test_num_2", "2"),
+        ("name", "Jöhn"),
+        ("list[0]", "a"),
+        ("list[1]", "b"),
+    ];
+
+    fn stream_one_byte_at_a_time(template: &str) -> Result<String, StreamError> {
+        let mut output = Vec::new();
+        replace_variables_stream(OneByteReader(template.as_bytes()), &mut output, &VALUES)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_stream_matches_replace_variables() {
+        let templates = [
+            include_str!("test_files/test_template.json.in"),
+            "%{{name}}%{{name}}",
+            "ä%{{name:-dëfault}} %{{unset:-dëfault\n}}ü",
+            "%%{{name}} %%%{{name}} %{%{{name}}%{{{name}}",
+            "%%{{%{{name}} %{{a:-%%{{!name}} %{{a%%{{name}} %%{{!%{{name}}}}",
+            "%{{unset | default(\"%%{{\")}} %%{{!name %%{{%{{a_%{{name}}",
+            "%{{name}",
+            "trailing %{{name:-unterminated",
+            "trailing %%",
+            "%{{1name}} %{{ name}} %{{name:x}}",
+            "%{{name.first[0]:-x}} %{{name.}} %{{name[0]}}%{{name[}}",
+            "%{{!name:-x}} %{{a_%{{name}}_b:-y}} %{{%{{name | lower}}}} %{{a%{{name}",
+            "%{{name | upper | default(\"x}\")}} %{{unset |default(\"a\")  }}%{{name |}}",
+            "a%{{#if name}}[%{{name}}%{{#unless test_num}}x%{{/unless}}]%{{else}}-%{{/if}}b %{{#if unset}}%{{/if",
+            "%{{#if name}}%{{/unless}}%{{#if  name}}%{{else}}%{{/}}%{{#unless test_num}}%{{else}}",
+            "[%{{#each list}}%{{@index}}:%{{@item}}%{{#sep}}, %{{/sep}}%{{/each}}] %{{#each list}}%{{#sep}}",
+        ];
+        for template in templates {
+            let expected = replace_variables(template, &VALUES).unwrap();
+            assert_eq!(stream_one_byte_at_a_time(template).unwrap(), expected);
+
+            let mut output = Vec::new();
+            replace_variables_stream(template.as_bytes(), &mut output, &VALUES).unwrap();
+            assert_eq!(output, expected.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_stream_errors() {
+        let template = "ä\n  %{{name}} %{{unset:?must be set}}";
+        let expected = replace_variables(template, &VALUES).unwrap_err();
+        match stream_one_byte_at_a_time(template).unwrap_err() {
+            StreamError::Substitution(err) => {
+                assert_eq!(err.span(), expected.span());
+                assert_eq!((err.line(), err.column()), (2, 13));
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+
+        let invalid_utf8: &[u8] = b"%{{name}} \xff";
+        let err = replace_variables_stream(invalid_utf8, io::sink(), &VALUES).unwrap_err();
+        assert!(matches!(err, StreamError::Io(e) if e.kind() == io::ErrorKind::InvalidData));
+        let truncated_utf8: &[u8] = b"%{{name}} \xc3";
+        let err = replace_variables_stream(truncated_utf8, io::sink(), &VALUES).unwrap_err();
+        assert!(matches!(err, StreamError::Io(e) if e.kind() == io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn test_stream_unclosed_placeholder() {
+        let template = format!("%{{{{name}}}} %{{{{a:-{}", "x}\n".repeat(2 * 1024 * 1024));
+        let mut output = Vec::new();
+        replace_variables_stream(template.as_bytes(), &mut output, &VALUES).unwrap();
+        let expected = format!("Jöhn{}", &template["%{{name}}".len()..]);
+        assert!(output == expected.as_bytes());
+
+        let template = format!(
+            "%{{{{a:-{}}}}} %{{{{name}}}}",
+            "x".repeat(MAX_PLACEHOLDER_LEN - 16)
+        );
+        let mut output = Vec::new();
+        replace_variables_stream(template.as_bytes(), &mut output, &VALUES).unwrap();
+        assert_eq!(
+            output,
+            replace_variables(&template, &VALUES).unwrap().as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_stream_unclosed_section() {
+        let template = format!(
+            "%{{{{#if name}}}}%{{{{name}}}}{}",
+            "a }}\n".repeat(MAX_SECTION_LEN / 4)
+        );
+        let mut output = Vec::new();
+        let err = replace_variables_stream(template.as_bytes(), &mut output, &VALUES).unwrap_err();
+        assert!(matches!(err, StreamError::Io(e) if e.kind() == io::ErrorKind::InvalidData));
+        assert!(output.is_empty());
+
+        // A section that is closed beyond the limit is not written as
+        // literal text either
+        let template = format!(
+            "a %{{{{name}}}}\n%{{{{#if unset}}}}%{{{{name}}}}{}%{{{{else}}}}b%{{{{/if}}}}",
+            "x\n".repeat(MAX_SECTION_LEN)
+        );
+        let mut output = Vec::new();
+        let err = replace_variables_stream(template.as_bytes(), &mut output, &VALUES).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "I/O error: 2:1: section %{{{{#if unset}}}} is not closed within {} bytes",
+                MAX_SECTION_LEN
+            )
+        );
+        assert_eq!(output, "a Jöhn\n".as_bytes());
+
+        let template = format!(
+            "%{{{{#if unset}}}}{}%{{{{/if}}}}%{{{{name}}}}",
+            "%{{x}}\n".repeat(MAX_SECTION_LEN / 16)
+        );
+        let mut output = Vec::new();
+        replace_variables_stream(template.as_bytes(), &mut output, &VALUES).unwrap();
+        assert_eq!(output, "Jöhn".as_bytes());
+    }
+}
@@ -0,0 +1,458 @@
+use crate::check::CheckReport;
+use crate::error::{Error, Errors, SyntaxError};
+use crate::escape::Escaper;
+use crate::filter::{Filters, BUILTIN};
+use crate::recursive::Expansion;
+use crate::resolver::Resolver;
+use crate::section::{Truthiness, DEFAULT_TRUTHINESS};
+use crate::template::{Position, Settings, Syntax, Template, DEFAULT_SYNTAX};
+use std::borrow::Cow;
+use std::fmt;
+
+/// Substitutes placeholders with other delimiters than %{{ }}, e.g.
+/// `${VAR}`, `{{ name }}` or `@VAR@`, with the same operators, filters and
+/// resolvers as [`replace_variables`](crate::replace_variables). Built with
+/// [`Substituter::builder`].
+///
+/// A substituter cannot render a stream:
+/// [`replace_variables_stream`](crate::replace_variables_stream) only
+/// recognizes the %{{ }} syntax, so a template with other delimiters has to
+/// be read into a string first and rendered with [`Substituter::replace`].
+///
+/// Example usage:
+/// ```
+/// use str_var_subst::{ShellQuote, Substituter};
+/// let values = [("name", "John"), ("greeting", "it's me")];
+///
+/// let shell = Substituter::builder().open("${").close("}").build().unwrap();
+/// let rendered = shell.replace("${name} ${unset:-none}", &values).unwrap();
+/// assert_eq!(rendered, "John none");
+///
+/// let mustache = Substituter::builder()
+///     .open("{{")
+///     .close("}}")
+///     .whitespace(true)
+///     .escaper(ShellQuote)
+///     .build()
+///     .unwrap();
+/// let rendered = mustache.replace("echo {{ greeting | upper }}", &values).unwrap();
+/// assert_eq!(rendered, r"echo 'IT'\''S ME'");
+///
+/// let autoconf = Substituter::builder().open("@").close("@").build().unwrap();
+/// assert_eq!(autoconf.replace("user@name@", &values).unwrap(), "userJohn");
+///
+/// let posix = Substituter::builder().shell().shell_format("$name").build().unwrap();
+/// let rendered = posix.replace("$name ${name:+is set} $greeting", &values).unwrap();
+/// assert_eq!(rendered, "John is set $greeting");
+/// ```
+///
+pub struct Substituter {
+    syntax: Syntax,
+    filters: Option<Filters>,
+    escaper: Option<Box<dyn Escaper>>,
+    /// Maximum depth of recursive expansion, `None` if it is off
+    max_depth: Option<usize>,
+    truthiness: Option<Truthiness>,
+}
+
+/// Configures a [`Substituter`]. Starts with the %{{ }} delimiters and `%%{{`
+/// as escape, the built-in filters and no escaper.
+pub struct SubstituterBuilder {
+    open: String,
+    close: String,
+    escape: Option<String>,
+    whitespace: bool,
+    /// Use the shell syntax, `Some(true)` together with the delimiters
+    shell: Option<bool>,
+    shell_format: Option<String>,
+    filters: Option<Filters>,
+    escaper: Option<Box<dyn Escaper>>,
+    max_depth: Option<usize>,
+    truthiness: Option<Truthiness>,
+}
+
+impl Substituter {
+    /// Starts configuring a substituter
+    pub fn builder() -> SubstituterBuilder {
+        SubstituterBuilder {
+            open: String::from("%{{"),
+            close: String::from("}}"),
+            escape: Some(String::from("%%{{")),
+            whitespace: false,
+            shell: None,
+            shell_format: None,
+            filters: None,
+            escaper: None,
+            max_depth: None,
+            truthiness: None,
+        }
+    }
+
+    /// Splits the template text into literal text and placeholders, see
+    /// [`Template::parse`]. The template can be rendered with
+    /// [`Substituter::render`] or with the methods of [`Template`].
+    pub fn parse(&self, template_text: &str) -> Template {
+        Template::parse_from(&self.syntax, template_text, &mut Position::default())
+    }
+
+    /// Renders a template with the filters and the escaper of the substituter
+    pub fn render<R>(&self, template: &Template, resolver: &R) -> Result<String, Error>
+    where
+        R: Resolver + ?Sized,
+    {
+        let settings = Settings {
+            filters: self.filters.as_ref(),
+            truthiness: self.truthiness.as_ref(),
+            ..Settings::for_resolver(&resolver)
+        };
+        template
+            .render_with_escape(
+                |var| match self.max_depth {
+                    Some(max_depth) => Expansion {
+                        syntax: &self.syntax,
+                        max_depth,
+                        settings,
+                    }
+                    .resolve(resolver, var, &[]),
+                    None => Ok(resolver.resolve(var)),
+                },
+                |_, value| match &self.escaper {
+                    Some(escaper) => Ok(escaper.escape(value)),
+                    None => Ok(Cow::Borrowed(value)),
+                },
+                settings,
+            )
+            .map_err(Errors::into_first)
+    }
+
+    /// Checks a template against the resolver without rendering it, with the
+    /// filters and the truthiness of the substituter, see [`check`](crate::check)
+    pub fn check<R>(&self, template: &Template, resolver: &R) -> CheckReport
+    where
+        R: Resolver + ?Sized,
+    {
+        let filters = self.filters.as_ref().unwrap_or(&BUILTIN);
+        let truthiness = self.truthiness.as_ref().unwrap_or(&DEFAULT_TRUTHINESS);
+        template.check_with(resolver, filters, truthiness)
+    }
+
+    /// Parses and renders the template text
+    pub fn replace<R>(&self, template_text: &str, resolver: &R) -> Result<String, Error>
+    where
+        R: Resolver + ?Sized,
+    {
+        self.render(&self.parse(template_text), resolver)
+    }
+}
+
+impl Default for Substituter {
+    /// The default %{{ }} syntax
+    fn default() -> Self {
+        Substituter {
+            syntax: DEFAULT_SYNTAX.clone(),
+            filters: None,
+            escaper: None,
+            max_depth: None,
+            truthiness: None,
+        }
+    }
+}
+
+impl fmt::Debug for Substituter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Substituter")
+            .field("pattern", &self.syntax.re.as_str())
+            .field("filters", &self.filters)
+            .field("escaper", &self.escaper.is_some())
+            .field("max_depth", &self.max_depth)
+            .field("truthiness", &self.truthiness)
+            .finish()
+    }
+}
+
+impl SubstituterBuilder {
+    /// Sets the opening delimiter, e.g. `${`. Unless set again with
+    /// [`escape`](SubstituterBuilder::escape), there is no escape for it.
+    pub fn open(mut self, open: impl Into<String>) -> Self {
+        self.open = open.into();
+        self.escape = None;
+        self
+    }
+
+    /// Sets the closing delimiter, e.g. `}`
+    pub fn close(mut self, close: impl Into<String>) -> Self {
+        self.close = close.into();
+        self
+    }
+
+    /// Sets the text that is rendered as a literal opening delimiter, e.g.
+    /// `$${` for `${`
+    pub fn escape(mut self, escape: impl Into<String>) -> Self {
+        self.escape = Some(escape.into());
+        self
+    }
+
+    /// Allows whitespace between the delimiters and the variable name, e.g.
+    /// `{{ name }}`
+    pub fn whitespace(mut self, whitespace: bool) -> Self {
+        self.whitespace = whitespace;
+        self
+    }
+
+    /// Uses the POSIX shell syntax of GNU envsubst instead of the delimiters:
+    /// `$VAR` and `${VAR}`, with the `${VAR:-word}`, `${VAR:=word}`,
+    /// `${VAR:+word}` and `${VAR:?message}` operators and their forms
+    /// without `:`, which only apply to unset variables, and `${!VAR}`
+    /// indirection. `${VAR:=word}` also
+    /// uses `word` as value of `VAR` for the rest of the template. The
+    /// argument is taken literally up to the first `}`, and there is no
+    /// escape for `$`.
+    pub fn shell(mut self) -> Self {
+        self.shell = Some(false);
+        self
+    }
+
+    /// Like [`shell`](SubstituterBuilder::shell) but keeps the %{{VAR}}
+    /// syntax, with filters and the `%%{{` escape, in addition to `$VAR` and
+    /// `${VAR}`
+    pub fn shell_and_default(mut self) -> Self {
+        self.shell = Some(true);
+        self
+    }
+
+    /// Only substitutes the variables named in `shell_format`, e.g.
+    /// `"$HOME ${USER}"`, like the SHELL-FORMAT argument of GNU envsubst.
+    /// Placeholders of other variables are left as they are.
+    pub fn shell_format(mut self, shell_format: impl Into<String>) -> Self {
+        self.shell_format = Some(shell_format.into());
+        self
+    }
+
+    /// Uses these filters instead of the built-in ones
+    pub fn filters(mut self, filters: Filters) -> Self {
+        self.filters = Some(filters);
+        self
+    }
+
+    /// Passes every value through the escaper before it is inserted
+    pub fn escaper<E>(mut self, escaper: E) -> Self
+    where
+        E: Escaper + 'static,
+    {
+        self.escaper = Some(Box::new(escaper));
+        self
+    }
+
+    /// Expands placeholders in values up to `max_depth` levels deep, see
+    /// [`replace_variables_recursive`](crate::replace_variables_recursive).
+    /// Values are escaped once, after they have been expanded.
+    pub fn recursive(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Decides which variables are true for `%{{#if variable}}` and
+    /// `%{{#unless variable}}` sections instead of the default rules
+    pub fn truthiness(mut self, truthiness: Truthiness) -> Self {
+        self.truthiness = Some(truthiness);
+        self
+    }
+
+    /// Builds the substituter, fails if a delimiter or the escape is empty
+    pub fn build(self) -> Result<Substituter, SyntaxError> {
+        let mut syntax = match self.shell {
+            Some(with_default) => Syntax::shell(with_default),
+            None => Syntax::new(
+                &self.open,
+                &self.close,
+                self.escape.as_deref(),
+                self.whitespace,
+            )?,
+        };
+        syntax.allowlist = self
+            .shell_format
+            .as_deref()
+            .map(Syntax::shell_format_variables);
+        Ok(Substituter {
+            syntax,
+            filters: self.filters,
+            escaper: self.escaper,
+            max_depth: self.max_depth,
+            truthiness: self.truthiness,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replace_variables;
+
+    #[test]
+    fn test_custom_delimiters() {
+        let values = [("a", "1"), ("b", "")];
+        let cases = [
+            (
+                "${",
+                "}",
+                "${a}${b:-x}${c-y} $a ${ a} $${a}",
+                "1xy $a ${ a} $1",
+            ),
+            ("{{", "}}", "{{a}}{{{a}}}{{a|default(\"z\")}}", "1{1}1"),
+            (
+                "@",
+                "@",
+                "@a@@b:-2@ user@example.com @@",
+                "12 user@example.com @@",
+            ),
+            ("<%=", "%>", "<%=a%><%= a %>", "1<%= a %>"),
+        ];
+        for (open, close, template, expected) in cases {
+            let substituter = Substituter::builder()
+                .open(open)
+                .close(close)
+                .build()
+                .unwrap();
+            assert_eq!(substituter.replace(template, &values).unwrap(), expected);
+        }
+
+        let substituter = Substituter::builder()
+            .open("${")
+            .close("}")
+            .escape("$${")
+            .build()
+            .unwrap();
+        assert_eq!(
+            substituter.replace("$${a} ${a}", &values).unwrap(),
+            "${a} 1"
+        );
+        let err = substituter
+            .replace("\n ${c:?c is needed}", &values)
+            .unwrap_err();
+        assert_eq!((err.name(), err.line(), err.column()), ("c", 2, 2));
+        assert_eq!(err.to_string(), "2:2: ${c}: c is needed");
+
+        let autoconf = Substituter::builder().open("@").close("@").build().unwrap();
+        let err = autoconf.replace("@x:?needed@", &values).unwrap_err();
+        assert_eq!(err.to_string(), "1:1: @x@: needed");
+        let mustache = Substituter::builder()
+            .open("{{")
+            .close("}}")
+            .whitespace(true)
+            .build()
+            .unwrap();
+        let report = mustache.check(&mustache.parse("{{ c | upper }}"), &values);
+        assert_eq!(
+            report.to_string(),
+            "1:1: {{ c }} is unset, replaced with an empty string\n"
+        );
+        let template = mustache.parse("{{#if a}}{{ c:? }}{{/if}}");
+        let err = mustache.render(&template, &values).unwrap_err();
+        assert_eq!(err.reference(), "{{ c }}");
+    }
+
+    #[test]
+    fn test_builder_options() {
+        let filters =
+            Filters::new().register("twice", |value: &str, _: &[String]| Ok(value.repeat(2)));
+        let substituter = Substituter::builder()
+            .open("{{")
+            .close("}}")
+            .whitespace(true)
+            .filters(filters)
+            .escaper(|value: &str| format!("[{}]", value))
+            .build()
+            .unwrap();
+        let template = substituter.parse("{{ a }} {{a | twice }} {{\n b | default(\"x\") }}");
+        assert_eq!(template.variables().len(), 3);
+        assert_eq!(
+            substituter.render(&template, &[("a", "1")]).unwrap(),
+            "[1] [11] [x]"
+        );
+
+        let template = "%{{a}} %%{{a}} %{{b | upper}}";
+        let values = [("a", "1"), ("b", "x")];
+        assert_eq!(
+            Substituter::builder()
+                .build()
+                .unwrap()
+                .replace(template, &values)
+                .unwrap(),
+            replace_variables(template, &values).unwrap()
+        );
+        assert_eq!(
+            Substituter::default().replace(template, &values).unwrap(),
+            "1 %{{a}} X"
+        );
+
+        for (open, close, escape) in [("", "}", None), ("${", "", None), ("${", "}", Some("${"))] {
+            let mut builder = Substituter::builder().open(open).close(close);
+            if let Some(escape) = escape {
+                builder = builder.escape(escape);
+            }
+            assert!(builder.build().is_err());
+        }
+    }
+
+    #[test]
+    fn test_shell_syntax() {
+        let values = [("a", "1"), ("empty", ""), ("ptr", "a")];
+        let shell = Substituter::builder().shell().build().unwrap();
+        let cases = [
+            (
+                "$a ${a} $a_b ${a}b $$a $ {a} $1 ${a }",
+                "1 1  1b $1 $ {a} $1 ${a }",
+            ),
+            ("${empty:-x} ${empty-x} ${unset-x}", "x  x"),
+            ("${empty:+x} ${empty+x} ${a:+x} ${unset+x}", " x x "),
+            ("${unset:=x} $unset ${unset:-y} ${empty=z}$empty", "x x x "),
+            ("${empty:=z} $empty %{{a}}", "z z %{{a}}"),
+            ("${!ptr} ${!empty:-x} $!ptr", "1 x $!ptr"),
+            ("${a:-{x}} ${unset:-{x}}", "1} {x}"),
+        ];
+        for (template, expected) in cases {
+            assert_eq!(shell.replace(template, &values).unwrap(), expected);
+        }
+        let err = shell
+            .replace("x\n ${unset:?is needed}", &values)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "2:2: ${unset}: is needed");
+        assert_eq!(
+            shell.parse("$a ${b:=c}").variables()[1].default.as_deref(),
+            Some("c")
+        );
+
+        let both = Substituter::builder().shell_and_default().build().unwrap();
+        assert_eq!(
+            both.replace("$a %{{a | upper}} %%{{a}} ${unset:-x}", &values)
+                .unwrap(),
+            "1 1 %{{a}} x"
+        );
+
+        let allowlisted = Substituter::builder()
+            .shell()
+            .shell_format("$a, ${empty}")
+            .build()
+            .unwrap();
+        assert_eq!(
+            allowlisted
+                .replace("$a ${empty:-x} $unset ${HOME}", &values)
+                .unwrap(),
+            "1 x $unset ${HOME}"
+        );
+        let recursive = Substituter::builder()
+            .shell()
+            .recursive(2)
+            .escaper(|value: &str| format!("'{}'", value))
+            .build()
+            .unwrap();
+        let nested = [("a", "$b"), ("b", "${c:-x}")];
+        assert_eq!(recursive.replace("$a", &nested).unwrap(), "'x'");
+
+        let allowlisted = Substituter::builder().shell_format("$b").build().unwrap();
+        assert_eq!(
+            allowlisted.replace("%{{a}} %{{b:-x}}", &values).unwrap(),
+            "%{{a}} x"
+        );
+    }
+}
@@ -0,0 +1,1158 @@
+use crate::error::{Error, ErrorKind, Errors, SyntaxError};
+use crate::filter::{self, FilterCall, Filters};
+use crate::resolver::Resolver;
+use crate::section::{Loop, LoopVariable, Section, SectionKind, Truthiness, DEFAULT_TRUTHINESS};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+/// Matches the `| filter | filter("argument", 1)` pipeline of a placeholder
+const FILTERS_PATTERN: &str = r#"(?:\s*\|\s*[a-zA-Z_]\w*(?:\(\s*(?:(?:"(?:[^"\\]|\\.)*"|-?[0-9]+(?:\.[0-9]+)?)\s*(?:,\s*(?:"(?:[^"\\]|\\.)*"|-?[0-9]+(?:\.[0-9]+)?)\s*)*)?\))?)+"#;
+
+/// Matches a variable name, or a dotted and indexed path such as `a.b[0].c`
+const NAME_PATTERN: &str = r"[a-zA-Z_]\w*(?:\.[a-zA-Z_]\w*|\[[0-9]+\])*";
+
+/// Matches the POSIX shell `${variable}`, `${variable:-word}`, `${!variable}`
+/// and `$variable` placeholders
+const SHELL_PATTERN: &str = r"\$\{(?P<shell_name>!?[a-zA-Z_]\w*)(?:(?P<shell_operator>:?[-=+?])(?P<shell_argument>[^}]*))?\}|\$(?P<bare_name>[a-zA-Z_]\w*)";
+
+lazy_static! {
+    /// The default %{{variable}} syntax, with `%%{{` as escape for a literal `%{{`
+    pub(crate) static ref DEFAULT_SYNTAX: Syntax = Syntax::new("%{{", "}}", Some("%%{{"), false).unwrap();
+}
+
+/// The delimiters of placeholders and the regex that matches them
+#[derive(Debug, Clone)]
+pub(crate) struct Syntax {
+    pub(crate) re: Regex,
+    /// What an escape, e.g. `%%{{`, is rendered as
+    open: String,
+    /// Only these variables are substituted, other placeholders are kept as
+    /// literal text
+    pub(crate) allowlist: Option<HashSet<String>>,
+}
+
+impl Syntax {
+    /// Builds the syntax for placeholders between `open` and `close`, with
+    /// `escape` as escape for a literal `open`, optionally allowing
+    /// whitespace around the variable name and filters
+    pub(crate) fn new(
+        open: &str,
+        close: &str,
+        escape: Option<&str>,
+        whitespace: bool,
+    ) -> Result<Syntax, SyntaxError> {
+        if open.is_empty() || close.is_empty() {
+            return Err(SyntaxError::new("delimiters must not be empty"));
+        }
+        if escape.is_some_and(|escape| escape.is_empty() || escape == open) {
+            return Err(SyntaxError::new(
+                "the escape must not be empty or the same as the opening delimiter",
+            ));
+        }
+        let space = if whitespace { r"\s*" } else { "" };
+        // A placeholder in the name of a placeholder, e.g. `%{{env}}` in
+        // `%{{config_%{{env}}}}`, one level deep
+        let nested = format!(
+            r"{open}{space}!?{name}(?::?[-?](?s:.)*?|{filters}\s*)?{space}{close}",
+            open = regex::escape(open),
+            close = regex::escape(close),
+            name = NAME_PATTERN,
+            filters = FILTERS_PATTERN,
+        );
+        // `#if variable`, `#unless variable`, `#each variable`, `#sep`,
+        // `else` and the closing `/if`, `/unless`, `/each` and `/sep`
+        let tags = format!(
+            r"{open}{space}(?:#(?:(?P<section>if|unless|each)\s+(?P<condition>@?{name})|(?P<separator>sep))|(?P<else>else)|/(?P<end>if|unless|each|sep)){space}{close}|",
+            open = regex::escape(open),
+            close = regex::escape(close),
+            name = NAME_PATTERN,
+        );
+        let pattern = format!(
+            r"{escape}{tags}{open}{space}(?P<name>!?(?:@?{name}|{nested})(?:{nested}|\w|\.[a-zA-Z_]\w*|\[[0-9]+\])*)(?:(?P<operator>:?[-?])(?P<argument>(?s:.)*?)|(?P<filters>{filters})\s*)?{space}{close}",
+            escape = escape
+                .map(|escape| format!("{}|", regex::escape(escape)))
+                .unwrap_or_default(),
+            tags = tags,
+            open = regex::escape(open),
+            close = regex::escape(close),
+            name = NAME_PATTERN,
+            nested = nested,
+            filters = FILTERS_PATTERN,
+        );
+        let re = Regex::new(&pattern).map_err(|e| SyntaxError::new(&e.to_string()))?;
+        Ok(Syntax {
+            re,
+            open: open.to_owned(),
+            allowlist: None,
+        })
+    }
+
+    /// Builds the POSIX shell syntax, `$variable` and `${variable}` with the
+    /// `-`, `=`, `+` and `?` operators, optionally together with the default
+    /// %{{variable}} syntax. Like in GNU envsubst, there is no escape for `$`.
+    pub(crate) fn shell(with_default: bool) -> Syntax {
+        let pattern = match with_default {
+            true => format!("{}|{}", DEFAULT_SYNTAX.re.as_str(), SHELL_PATTERN),
+            false => SHELL_PATTERN.to_owned(),
+        };
+        Syntax {
+            re: Regex::new(&pattern).unwrap(),
+            open: DEFAULT_SYNTAX.open.clone(),
+            allowlist: None,
+        }
+    }
+
+    /// Names of the `$variable` and `${variable}` references in a GNU envsubst
+    /// SHELL-FORMAT, e.g. `'$HOME ${USER}'`
+    pub(crate) fn shell_format_variables(shell_format: &str) -> HashSet<String> {
+        lazy_static! {
+            static ref SHELL_RE: Regex = Regex::new(SHELL_PATTERN).unwrap();
+        }
+        SHELL_RE
+            .captures_iter(shell_format)
+            .filter_map(|caps| caps.name("shell_name").or_else(|| caps.name("bare_name")))
+            .map(|name| name.as_str().to_owned())
+            .collect()
+    }
+
+    fn allows(&self, name: &str) -> bool {
+        self.allowlist
+            .as_ref()
+            .is_none_or(|allowlist| allowlist.contains(name))
+    }
+}
+
+/// A template that has been split into literal text and %{{variable}}
+/// placeholders ahead of time, so it can be rendered many times without
+/// scanning the text again.
+///
+/// Example usage:
+/// ```
+/// use str_var_subst::Template;
+/// let template = Template::parse("Hi my name is %{{name}}%{{suffix:-!}}");
+/// for name in ["John", "Jane"] {
+///     let rendered = template.render(&[("name", name)]).unwrap();
+///     assert_eq!(rendered, format!("Hi my name is {}!", name));
+/// }
+/// ```
+///
+#[derive(Debug, Clone)]
+pub struct Template {
+    segments: Vec<Segment>,
+    literal_len: usize,
+    /// Number of placeholders, including the ones in sections
+    placeholder_count: usize,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Segment {
+    Literal(String),
+    Placeholder(Placeholder),
+    Section(Section),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Placeholder {
+    /// The name as written, e.g. `config_%{{env}}` or `!ptr`
+    pub(crate) name: String,
+    /// The placeholder as written without its operator and filters, e.g.
+    /// `%{{name}}` for `%{{name:-default}}` or `${name}` for `${name:?msg}`
+    pub(crate) reference: String,
+    /// How the name is computed, `None` if it is used as it is
+    pub(crate) dynamic: Option<DynamicName>,
+    pub(crate) operator: Option<Operator>,
+    /// The `| filter` pipeline, a placeholder has either an operator or filters
+    pub(crate) filters: Vec<FilterCall>,
+    pub(crate) span: Range<usize>,
+    pub(crate) position: (usize, usize),
+}
+
+/// A section whose closing tag has not been reached yet while parsing
+struct OpenSection {
+    kind: SectionKind,
+    condition: String,
+    /// The opening tag as written, kept as literal text if the section is
+    /// never closed
+    tag: String,
+    start: Position,
+    /// The segments before the section
+    outer: Vec<Segment>,
+    /// The segments before `%{{else}}` and the `%{{else}}` tag as written,
+    /// once it has been reached
+    body: Option<(Vec<Segment>, String)>,
+}
+
+/// The name of a `%{{config_%{{env}}}}` or `%{{!ptr}}` placeholder, computed
+/// while rendering
+#[derive(Debug, Clone)]
+pub(crate) struct DynamicName {
+    /// `!`: the value of the named variable is the name of the variable
+    indirect: bool,
+    /// The name with its nested placeholders, without `!`
+    nested: Option<Template>,
+}
+
+/// The shell-style operator of a placeholder, e.g. `:-default`
+#[derive(Debug, Clone)]
+pub(crate) struct Operator {
+    /// The operator starts with `:`, so empty values count as unset
+    colon: bool,
+    pub(crate) action: Action,
+    pub(crate) argument: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Action {
+    /// `-`: substitute the argument
+    Default,
+    /// `=`: substitute the argument and use it as the value of the variable
+    /// for the rest of the template (shell syntax only)
+    Assign,
+    /// `+`: substitute the argument if the variable is set, an empty string
+    /// otherwise (shell syntax only)
+    Alternative,
+    /// `?`: fail with the argument as message
+    Required,
+}
+
+/// A %{{variable}} placeholder found in a template, see [`Template::variables`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct VariableRef {
+    /// Name of the variable, without the %{{ }} delimiters
+    pub name: String,
+    /// The placeholder as written, with its delimiters but without its
+    /// operator and filters, e.g. `%{{name}}` or `${name}`
+    pub reference: String,
+    /// Byte range of the whole placeholder in the template text
+    pub span: Range<usize>,
+    /// 1-based line of the placeholder
+    pub line: usize,
+    /// 1-based column (in characters) of the placeholder
+    pub column: usize,
+    /// Default value given with `%{{variable:-default}}` or `%{{variable-default}}`
+    pub default: Option<String>,
+    /// The placeholder is marked as required with `%{{variable:?message}}` or
+    /// `%{{variable?message}}`
+    pub required: bool,
+    /// Names of the filters of a `%{{variable | filter}}` pipeline, in order
+    pub filters: Vec<String>,
+}
+
+/// Provides the value of a variable while rendering, `None` if it is unset
+type ReplacementStrategy<'a, 'r> = dyn Fn(&str) -> Result<Option<Cow<'r, str>>, ErrorKind> + 'a;
+
+/// Escapes the value of the placeholder with the given index, see
+/// [`Template::render_with_escape`]
+type EscapeStrategy<'a> = dyn for<'v> Fn(usize, &'v str) -> Result<Cow<'v, str>, ErrorKind> + 'a;
+
+/// Provides the number of items of a list-valued variable, see
+/// [`Resolver::list_len`]. Implemented for references to resolvers, so
+/// `&&R` can be used as `&dyn ListLen` even if `R` is unsized.
+pub(crate) trait ListLen {
+    fn list_len(&self, name: &str) -> Option<usize>;
+}
+
+impl<R> ListLen for &R
+where
+    R: Resolver + ?Sized,
+{
+    fn list_len(&self, name: &str) -> Option<usize> {
+        (**self).list_len(name)
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+pub(crate) struct Settings<'f> {
+    /// Stop at the first error instead of collecting all of them
+    pub(crate) fail_fast: bool,
+    /// Report unset variables without a default as errors instead of
+    /// replacing them with an empty string
+    pub(crate) error_on_unset: bool,
+    /// Filters for `%{{variable | filter}}` pipelines, the built-in ones if `None`
+    pub(crate) filters: Option<&'f Filters>,
+    /// Which variables are true for `%{{#if variable}}` sections, the
+    /// default rules if `None`
+    pub(crate) truthiness: Option<&'f Truthiness>,
+    /// Lengths of the lists of `%{{#each variable}}` sections, there are no
+    /// lists if `None`
+    pub(crate) list_len: Option<&'f dyn ListLen>,
+}
+
+impl<'f> Settings<'f> {
+    /// The settings of rendering with a resolver: stop at the first error,
+    /// with the lists of the resolver
+    pub(crate) fn for_resolver<R>(resolver: &'f &'f R) -> Settings<'f>
+    where
+        R: Resolver + ?Sized,
+    {
+        Settings {
+            fail_fast: true,
+            list_len: Some(resolver),
+            ..Settings::default()
+        }
+    }
+}
+
+impl Template {
+    /// Splits the template text into literal text, placeholders and
+    /// `%{{#if variable}}` and `%{{#each list}}` sections.
+    /// `%%{{` is an escape for a literal `%{{` and never starts a placeholder.
+    pub fn parse(template_text: &str) -> Template {
+        Template::parse_from(&DEFAULT_SYNTAX, template_text, &mut Position::default())
+    }
+
+    /// Parses a piece of a larger text starting at `position`, which is
+    /// advanced to the end of the piece
+    pub(crate) fn parse_from(
+        syntax: &Syntax,
+        template_text: &str,
+        position: &mut Position,
+    ) -> Template {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut last_end = 0;
+        // Sections whose closing tag has not been reached yet, innermost last
+        let mut open_sections: Vec<OpenSection> = Vec::new();
+
+        for caps in syntax.re.captures_iter(template_text) {
+            let matched = caps.get(0).unwrap();
+            let text_before = &template_text[last_end..matched.start()];
+            literal.push_str(text_before);
+            position.advance(text_before);
+            last_end = matched.end();
+
+            if let Some(keyword) = caps.name("section").or_else(|| caps.name("separator")) {
+                // `#sep` depends on whether the item is the last one
+                let condition = caps.name("condition").map_or("@last", |name| name.as_str());
+                if caps
+                    .name("condition")
+                    .is_none_or(|_| syntax.allows(condition))
+                {
+                    flush_literal(&mut literal, &mut segments);
+                    open_sections.push(OpenSection {
+                        kind: SectionKind::from_keyword(keyword.as_str()),
+                        condition: condition.to_owned(),
+                        tag: matched.as_str().to_owned(),
+                        start: position.clone(),
+                        outer: std::mem::take(&mut segments),
+                        body: None,
+                    });
+                    position.advance(matched.as_str());
+                    continue;
+                }
+            }
+            if caps.name("else").is_some() {
+                if let Some(section) = open_sections
+                    .last_mut()
+                    .filter(|section| section.body.is_none())
+                {
+                    flush_literal(&mut literal, &mut segments);
+                    section.body =
+                        Some((std::mem::take(&mut segments), matched.as_str().to_owned()));
+                    position.advance(matched.as_str());
+                    continue;
+                }
+            }
+            if let Some(keyword) = caps.name("end") {
+                if open_sections
+                    .last()
+                    .is_some_and(|section| section.kind.keyword() == keyword.as_str())
+                {
+                    flush_literal(&mut literal, &mut segments);
+                    let section = open_sections.pop().unwrap();
+                    let (body, otherwise) = match section.body {
+                        Some((body, _)) => (body, std::mem::take(&mut segments)),
+                        None => (std::mem::take(&mut segments), Vec::new()),
+                    };
+                    position.advance(matched.as_str());
+                    segments = section.outer;
+                    segments.push(Segment::Section(Section {
+                        kind: section.kind,
+                        condition: section.condition,
+                        tag: section.tag,
+                        body: Template::from_segments(body),
+                        otherwise: Template::from_segments(otherwise),
+                        span: section.start.offset..position.offset,
+                        position: (section.start.line, section.start.column),
+                    }));
+                    continue;
+                }
+            }
+
+            let name = caps
+                .name("name")
+                .or_else(|| caps.name("shell_name"))
+                .or_else(|| caps.name("bare_name"));
+            let is_tag = ["section", "separator", "else", "end"]
+                .iter()
+                .any(|group| caps.name(group).is_some());
+            let name = match name {
+                Some(name) if syntax.allows(name.as_str()) => name,
+                // disallowed placeholders and unbalanced tags
+                _ if name.is_some() || is_tag => {
+                    literal.push_str(matched.as_str());
+                    position.advance(matched.as_str());
+                    continue;
+                }
+                _ => {
+                    literal.push_str(&syntax.open);
+                    position.advance(matched.as_str());
+                    continue;
+                }
+            };
+            flush_literal(&mut literal, &mut segments);
+            let (indirect, bare_name) = match name.as_str().strip_prefix('!') {
+                Some(bare_name) => (true, bare_name),
+                None => (false, name.as_str()),
+            };
+            let nested = bare_name.contains(syntax.open.as_str()).then(|| {
+                let mut name_position = position.clone();
+                name_position
+                    .advance(&template_text[matched.start()..name.end() - bare_name.len()]);
+                Template::parse_from(syntax, bare_name, &mut name_position)
+            });
+            let dynamic =
+                (indirect || nested.is_some()).then_some(DynamicName { indirect, nested });
+
+            let argument = caps
+                .name("argument")
+                .or_else(|| caps.name("shell_argument"));
+            let operator = caps
+                .name("operator")
+                .or_else(|| caps.name("shell_operator"))
+                .map(|operator| Operator {
+                    colon: operator.as_str().starts_with(':'),
+                    action: match operator.as_str().trim_start_matches(':') {
+                        "-" => Action::Default,
+                        "=" => Action::Assign,
+                        "+" => Action::Alternative,
+                        _ => Action::Required,
+                    },
+                    argument: argument.map_or("", |argument| argument.as_str()).to_owned(),
+                });
+            let filters = caps
+                .name("filters")
+                .map(|filters| filter::parse_pipeline(filters.as_str()))
+                .unwrap_or_default();
+            let rest_start = ["argument", "shell_argument", "filters"]
+                .iter()
+                .filter_map(|group| caps.name(group))
+                .map(|rest| rest.end())
+                .max()
+                .unwrap_or(name.end());
+            let reference = format!(
+                "{}{}",
+                &template_text[matched.start()..name.end()],
+                &template_text[rest_start..matched.end()]
+            );
+            segments.push(Segment::Placeholder(Placeholder {
+                name: name.as_str().to_owned(),
+                reference,
+                dynamic,
+                operator,
+                filters,
+                span: position.offset..position.offset + matched.len(),
+                position: (position.line, position.column),
+            }));
+            position.advance(matched.as_str());
+        }
+
+        literal.push_str(&template_text[last_end..]);
+        position.advance(&template_text[last_end..]);
+        flush_literal(&mut literal, &mut segments);
+        // Sections that are never closed are literal text
+        while let Some(section) = open_sections.pop() {
+            let mut outer = section.outer;
+            push_segment(&mut outer, Segment::Literal(section.tag));
+            if let Some((body, else_tag)) = section.body {
+                for segment in body {
+                    push_segment(&mut outer, segment);
+                }
+                push_segment(&mut outer, Segment::Literal(else_tag));
+            }
+            for segment in segments {
+                push_segment(&mut outer, segment);
+            }
+            segments = outer;
+        }
+        Template::from_segments(segments)
+    }
+
+    fn from_segments(segments: Vec<Segment>) -> Template {
+        let literal_len = segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(text) => text.len(),
+                _ => 0,
+            })
+            .sum();
+        let placeholder_count = segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(_) => 0,
+                Segment::Placeholder(_) => 1,
+                Segment::Section(section) => {
+                    section.body.placeholder_count + section.otherwise.placeholder_count
+                }
+            })
+            .sum();
+        Template {
+            segments,
+            literal_len,
+            placeholder_count,
+        }
+    }
+
+    /// Every placeholder of the template, in the order they appear in the text.
+    /// A variable used several times is listed once per occurrence.
+    /// Placeholders in sections are listed once, whether the section would be
+    /// rendered or not, the variables of the tags are not listed.
+    pub fn variables(&self) -> Vec<VariableRef> {
+        self.placeholders()
+            .into_iter()
+            .map(Placeholder::to_variable_ref)
+            .collect()
+    }
+
+    /// Every placeholder, including the ones in sections, in the order they
+    /// appear in the text
+    pub(crate) fn placeholders(&self) -> Vec<&Placeholder> {
+        let mut placeholders = Vec::with_capacity(self.placeholder_count);
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(_) => {}
+                Segment::Placeholder(placeholder) => placeholders.push(placeholder),
+                Segment::Section(section) => {
+                    placeholders.extend(section.body.placeholders());
+                    placeholders.extend(section.otherwise.placeholders());
+                }
+            }
+        }
+        placeholders
+    }
+
+    /// Renders the template with the values provided by the resolver, with
+    /// the same semantics as [`replace_variables`](crate::replace_variables)
+    pub fn render<R>(&self, resolver: &R) -> Result<String, Error>
+    where
+        R: Resolver + ?Sized,
+    {
+        let settings = Settings::for_resolver(&resolver);
+        self.render_with(|var| Ok(resolver.resolve(var)), settings)
+            .map_err(Errors::into_first)
+    }
+
+    /// Renders the template, reporting every unset variable without a default
+    /// value as an error, like [`envsubst_strict`](crate::envsubst_strict)
+    pub fn render_strict<R>(&self, resolver: &R) -> Result<String, Errors>
+    where
+        R: Resolver + ?Sized,
+    {
+        let settings = Settings {
+            fail_fast: false,
+            error_on_unset: true,
+            ..Settings::for_resolver(&resolver)
+        };
+        self.render_with(|var| Ok(resolver.resolve(var)), settings)
+    }
+
+    /// Renders the template like [`render`](Template::render) but with the
+    /// filters of the registry, see [`replace_variables_with_filters`](crate::replace_variables_with_filters)
+    pub fn render_with_filters<R>(&self, resolver: &R, filters: &Filters) -> Result<String, Error>
+    where
+        R: Resolver + ?Sized,
+    {
+        let settings = Settings {
+            filters: Some(filters),
+            ..Settings::for_resolver(&resolver)
+        };
+        self.render_with(|var| Ok(resolver.resolve(var)), settings)
+            .map_err(Errors::into_first)
+    }
+
+    pub(crate) fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    pub(crate) fn render_with<'r, F>(
+        &self,
+        replacement_strategy: F,
+        settings: Settings<'_>,
+    ) -> Result<String, Errors>
+    where
+        F: Fn(&str) -> Result<Option<Cow<'r, str>>, ErrorKind>,
+    {
+        self.render_with_escape(
+            replacement_strategy,
+            |_, value| Ok(Cow::Borrowed(value)),
+            settings,
+        )
+    }
+
+    /// Same as `render_with` but every value is passed through `escape`
+    /// together with the index of its placeholder (counting placeholders only,
+    /// including the ones in sections that are not rendered) before it is
+    /// inserted. `escape` may refuse a value that cannot be inserted safely.
+    pub(crate) fn render_with_escape<'r, F, E>(
+        &self,
+        replacement_strategy: F,
+        escape: E,
+        settings: Settings<'_>,
+    ) -> Result<String, Errors>
+    where
+        F: Fn(&str) -> Result<Option<Cow<'r, str>>, ErrorKind>,
+        E: for<'v> Fn(usize, &'v str) -> Result<Cow<'v, str>, ErrorKind>,
+    {
+        let mut render = Render {
+            escape: &escape,
+            settings,
+            filters: settings.filters.unwrap_or(&filter::BUILTIN),
+            truthiness: settings.truthiness.unwrap_or(&DEFAULT_TRUTHINESS),
+            assigned: HashMap::new(),
+            loops: Vec::new(),
+            errors: Vec::new(),
+        };
+        let mut result = String::with_capacity(self.literal_len);
+        render.template(self, &replacement_strategy, 0, &mut result);
+
+        if !render.errors.is_empty() {
+            return Err(Errors(render.errors));
+        }
+        Ok(result)
+    }
+}
+
+/// State of rendering a template, shared with its sections
+struct Render<'a> {
+    escape: &'a EscapeStrategy<'a>,
+    settings: Settings<'a>,
+    filters: &'a Filters,
+    truthiness: &'a Truthiness,
+    /// Values assigned with `${variable:=word}`, they shadow the resolver
+    assigned: HashMap<String, String>,
+    /// The items being rendered of the enclosing `%{{#each}}` sections,
+    /// innermost last
+    loops: Vec<Loop>,
+    errors: Vec<Error>,
+}
+
+impl Render<'_> {
+    /// Renders the segments of the template into `result`, `first_index` is
+    /// the index of its first placeholder
+    fn template<'r>(
+        &mut self,
+        template: &Template,
+        replacement_strategy: &ReplacementStrategy<'_, 'r>,
+        first_index: usize,
+        result: &mut String,
+    ) {
+        let mut index = first_index;
+        for segment in &template.segments {
+            if self.settings.fail_fast && !self.errors.is_empty() {
+                return;
+            }
+            match segment {
+                Segment::Literal(text) => result.push_str(text),
+                Segment::Placeholder(placeholder) => {
+                    self.placeholder(placeholder, replacement_strategy, index, result);
+                    index += 1;
+                }
+                Segment::Section(section) => {
+                    let rendered = match section.kind {
+                        SectionKind::Each => {
+                            self.each(section, replacement_strategy, index, result);
+                            Ok(())
+                        }
+                        _ => self
+                            .condition(section, replacement_strategy)
+                            .map(|condition| match condition {
+                                true => self.template(
+                                    &section.body,
+                                    replacement_strategy,
+                                    index,
+                                    result,
+                                ),
+                                false => self.template(
+                                    &section.otherwise,
+                                    replacement_strategy,
+                                    index + section.body.placeholder_count,
+                                    result,
+                                ),
+                            }),
+                    };
+                    if let Err(kind) = rendered {
+                        self.errors.push(Error::new(
+                            kind,
+                            &section.condition,
+                            &section.tag,
+                            section.span.clone(),
+                            section.position,
+                        ));
+                    }
+                    index += section.body.placeholder_count + section.otherwise.placeholder_count;
+                }
+            }
+        }
+    }
+
+    /// Whether the body of an `#if`, `#unless` or `#sep` section is rendered,
+    /// see [`Section::holds`]
+    fn condition<'r>(
+        &self,
+        section: &Section,
+        replacement_strategy: &ReplacementStrategy<'_, 'r>,
+    ) -> Result<bool, ErrorKind> {
+        let value = self.lookup(&section.condition, replacement_strategy)?;
+        Ok(section.holds(value.as_deref(), self.truthiness, || {
+            self.list(&section.condition).1
+        }))
+    }
+
+    /// Renders the body of an `#each` section once per item, or the part
+    /// after `%{{else}}` if the list is empty
+    fn each<'r>(
+        &mut self,
+        section: &Section,
+        replacement_strategy: &ReplacementStrategy<'_, 'r>,
+        index: usize,
+        result: &mut String,
+    ) {
+        let (list, len) = self.list(&section.condition);
+        if len == 0 {
+            let index = index + section.body.placeholder_count;
+            self.template(&section.otherwise, replacement_strategy, index, result);
+        }
+        for item in 0..len {
+            self.loops.push(Loop {
+                list: list.clone(),
+                index: item,
+                len,
+            });
+            self.template(&section.body, replacement_strategy, index, result);
+            self.loops.pop();
+        }
+    }
+
+    /// Path and number of items of a list-valued variable
+    fn list(&self, name: &str) -> (String, usize) {
+        let path = match self.loops.last().and_then(|current| current.variable(name)) {
+            Some(LoopVariable::Path(path)) => path,
+            Some(LoopVariable::Value(_)) => return (String::new(), 0),
+            None => name.to_owned(),
+        };
+        let len = self
+            .settings
+            .list_len
+            .and_then(|list_len| list_len.list_len(&path))
+            .unwrap_or(0);
+        (path, len)
+    }
+
+    /// Value of a variable: a loop variable, a value assigned with
+    /// `${variable:=word}` or the value provided by the strategy
+    fn lookup<'r>(
+        &self,
+        name: &str,
+        replacement_strategy: &ReplacementStrategy<'_, 'r>,
+    ) -> Result<Option<Cow<'r, str>>, ErrorKind> {
+        let name = match self.loops.last().and_then(|current| current.variable(name)) {
+            Some(LoopVariable::Value(value)) => return Ok(value.map(Cow::Owned)),
+            Some(LoopVariable::Path(path)) => Cow::Owned(path),
+            None => Cow::Borrowed(name),
+        };
+        match self.assigned.get(name.as_ref()) {
+            Some(value) => Ok(Some(Cow::Owned(value.clone()))),
+            None => replacement_strategy(&name),
+        }
+    }
+
+    fn placeholder<'r>(
+        &mut self,
+        placeholder: &Placeholder,
+        replacement_strategy: &ReplacementStrategy<'_, 'r>,
+        index: usize,
+        result: &mut String,
+    ) {
+        let value = placeholder
+            .variable_name(&|var| self.lookup(var, replacement_strategy), self.settings)
+            .and_then(|name| {
+                let value = match &name {
+                    Some(name) => self.lookup(name, replacement_strategy)?,
+                    None => None,
+                };
+                let value = match &placeholder.operator {
+                    Some(operator) => operator.apply(value)?,
+                    None if placeholder.filters.is_empty() => value,
+                    None => self.filters.apply(&placeholder.filters, value)?,
+                };
+                if let (Some(operator), Some(name), Some(value)) =
+                    (&placeholder.operator, name, &value)
+                {
+                    if matches!(operator.action, Action::Assign) {
+                        self.assigned.insert(name.into_owned(), value.to_string());
+                    }
+                }
+                Ok(value)
+            });
+        let kind = match value {
+            Ok(Some(value)) => match (self.escape)(index, &value) {
+                Ok(escaped) => {
+                    result.push_str(&escaped);
+                    return;
+                }
+                Err(kind) => kind,
+            },
+            Ok(None) if !self.settings.error_on_unset => return,
+            Ok(None) => ErrorKind::Unset,
+            Err(kind) => kind,
+        };
+        self.errors.push(Error::new(
+            kind,
+            &placeholder.name,
+            &placeholder.reference,
+            placeholder.span.clone(),
+            placeholder.position,
+        ));
+    }
+}
+
+/// Moves the literal text collected so far into a segment
+fn flush_literal(literal: &mut String, segments: &mut Vec<Segment>) {
+    if !literal.is_empty() {
+        push_segment(segments, Segment::Literal(std::mem::take(literal)));
+    }
+}
+
+/// Appends a segment, merging adjacent literal text
+fn push_segment(segments: &mut Vec<Segment>, segment: Segment) {
+    if let (Some(Segment::Literal(last)), Segment::Literal(text)) = (segments.last_mut(), &segment)
+    {
+        last.push_str(text);
+        return;
+    }
+    segments.push(segment);
+}
+
+impl Placeholder {
+    /// Name of the variable to substitute: the name with its nested
+    /// placeholders rendered and, for `%{{!ptr}}`, the value of the named
+    /// variable. `None` if the variable named by `%{{!ptr}}` is unset.
+    pub(crate) fn variable_name<'r>(
+        &self,
+        replacement_strategy: &ReplacementStrategy<'_, 'r>,
+        settings: Settings<'_>,
+    ) -> Result<Option<Cow<'_, str>>, ErrorKind> {
+        let dynamic = match &self.dynamic {
+            Some(dynamic) => dynamic,
+            None => return Ok(Some(Cow::Borrowed(&self.name))),
+        };
+        let name = match &dynamic.nested {
+            Some(nested) => {
+                let settings = Settings {
+                    fail_fast: true,
+                    ..settings
+                };
+                let name = nested
+                    .render_with(replacement_strategy, settings)
+                    .map_err(|errors| ErrorKind::Resolver(Box::new(errors.into_first())))?;
+                Cow::Owned(name)
+            }
+            None => Cow::Borrowed(self.name.trim_start_matches('!')),
+        };
+        if !dynamic.indirect {
+            return Ok(Some(name));
+        }
+        Ok(replacement_strategy(&name)?.map(|target| Cow::Owned(target.into_owned())))
+    }
+
+    pub(crate) fn to_variable_ref(&self) -> VariableRef {
+        let operator = self.operator.as_ref();
+        VariableRef {
+            name: self.name.clone(),
+            reference: self.reference.clone(),
+            span: self.span.clone(),
+            line: self.position.0,
+            column: self.position.1,
+            default: operator
+                .filter(|operator| matches!(operator.action, Action::Default | Action::Assign))
+                .map(|operator| operator.argument.clone())
+                .or_else(|| self.default_filter().map(str::to_owned)),
+            required: operator.is_some_and(|operator| matches!(operator.action, Action::Required)),
+            filters: self.filters.iter().map(|call| call.name.clone()).collect(),
+        }
+    }
+
+    /// Argument of the first `default("x")` filter of the pipeline
+    pub(crate) fn default_filter(&self) -> Option<&str> {
+        self.filters
+            .iter()
+            .find(|call| call.name == "default")
+            .and_then(|call| call.args.first())
+            .map(String::as_str)
+    }
+}
+
+impl Operator {
+    /// Applies the operator of a `%{{variable:-default}}`, `%{{variable-default}}`,
+    /// `%{{variable:?message}}` or `%{{variable?message}}` placeholder, or of
+    /// their `${variable:=word}` and `${variable:+word}` shell counterparts,
+    /// to the resolved value
+    pub(crate) fn apply<'a>(
+        &'a self,
+        value: Option<Cow<'a, str>>,
+    ) -> Result<Option<Cow<'a, str>>, ErrorKind> {
+        match (self.action, self.takes_effect(value.as_deref())) {
+            (Action::Alternative, true) => Ok(Some(Cow::Borrowed(""))),
+            (Action::Alternative, false) => Ok(Some(Cow::Borrowed(&self.argument))),
+            (_, false) => Ok(value),
+            (Action::Default | Action::Assign, true) => Ok(Some(Cow::Borrowed(&self.argument))),
+            (Action::Required, true) => Err(ErrorKind::Required(self.message())),
+        }
+    }
+
+    /// Whether the variable counts as unset for the operator: it is unset,
+    /// or empty and the operator starts with `:`
+    pub(crate) fn takes_effect(&self, value: Option<&str>) -> bool {
+        match value {
+            Some(value) => value.is_empty() && self.colon,
+            None => true,
+        }
+    }
+
+    /// Error message of a `%{{variable:?message}}` placeholder
+    pub(crate) fn message(&self) -> String {
+        match self.argument.as_str() {
+            "" => String::from("required variable is not set"),
+            message => message.to_owned(),
+        }
+    }
+}
+
+/// Byte offset and 1-based line and column tracked while walking through
+/// the template text
+#[derive(Clone)]
+pub(crate) struct Position {
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+impl Position {
+    pub(crate) fn line_column(&self) -> (usize, usize) {
+        (self.line, self.column)
+    }
+
+    fn advance(&mut self, text: &str) {
+        self.offset += text.len();
+        for c in text.chars() {
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_segments() {
+        let template =
+            Template::parse("a %{{b}}\n%{{c:-d}}%{{e:?}}f %{{1g}}\n%{{#if h}}%{{i}}%{{/if}}");
+        let kinds: Vec<String> = template
+            .segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(text) => format!("literal {:?}", text),
+                Segment::Placeholder(p) => {
+                    format!("var {} at {:?} {:?}", p.name, p.span, p.position)
+                }
+                Segment::Section(s) => format!(
+                    "section {} at {:?} {:?} with {:?}",
+                    s.condition,
+                    s.span,
+                    s.position,
+                    s.body.placeholders()[0].name
+                ),
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                r#"literal "a ""#,
+                "var b at 2..8 (1, 3)",
+                r#"literal "\n""#,
+                "var c at 9..18 (2, 1)",
+                "var e at 18..26 (2, 10)",
+                r#"literal "f %{{1g}}\n""#,
+                r#"section h at 36..60 (3, 1) with "i""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_escapes() {
+        let values = [("name", "John"), ("empty", "")];
+        let cases = [
+            ("%%{{name}}", "%{{name}}"),
+            ("%%{{name}}%{{name}}", "%{{name}}John"),
+            ("%{{name}}%%{{name}}", "John%{{name}}"),
+            ("%%{{%{{name}}}}", "%{{John}}"),
+            ("%%%{{name}}", "%%{{name}}"),
+            ("%%%%{{name}}", "%%%{{name}}"),
+            ("%%{{", "%{{"),
+            ("a %%{{empty:-x}} b", "a %{{empty:-x}} b"),
+            ("%%{{name:?%{{name}}", "%{{name:?John"),
+            ("%%{{ not a variable }}", "%{{ not a variable }}"),
+            ("%%{", "%%{"),
+        ];
+        for (template, expected) in cases {
+            let rendered = Template::parse(template).render(&values).unwrap();
+            assert_eq!(rendered, expected, "rendering {:?}", template);
+        }
+
+        let template = Template::parse("%%{{a}}\n%{{b}}");
+        assert_eq!(template.segments.len(), 2);
+        match &template.segments[1] {
+            Segment::Placeholder(p) => assert_eq!((p.span.clone(), p.position), (8..14, (2, 1))),
+            segment => panic!("unexpected segment {:?}", segment),
+        }
+    }
+
+    #[test]
+    fn test_variables() {
+        let template_text = "%{{a}} %%{{b}}\n  %{{c:-x}}%{{a?}}%{{d-}}";
+        let variables = Template::parse(template_text).variables();
+        let summary: Vec<_> = variables
+            .iter()
+            .map(|v| {
+                (
+                    v.name.as_str(),
+                    &template_text[v.span.clone()],
+                    (v.line, v.column),
+                    v.default.as_deref(),
+                    v.required,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("a", "%{{a}}", (1, 1), None, false),
+                ("c", "%{{c:-x}}", (2, 3), Some("x"), false),
+                ("a", "%{{a?}}", (2, 12), None, true),
+                ("d", "%{{d-}}", (2, 19), Some(""), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_filters() {
+        let template_text =
+            "%{{a | trim | default(\"x\")}} %{{a:-b | upper}} %{{a | nope(1, \"2\")}} %{{a | upper(}} %{{a|}}";
+        let variables = Template::parse(template_text).variables();
+        let summary: Vec<_> = variables
+            .iter()
+            .map(|v| (v.default.as_deref(), v.filters.join(",")))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (Some("x"), String::from("trim,default")),
+                (Some("b | upper"), String::new()),
+                (None, String::from("nope")),
+            ]
+        );
+
+        let values = [("a", " a ")];
+        let err = Template::parse(template_text).render(&values).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "1:48: filter nope failed for %{{a}}: unknown filter"
+        );
+        let template = Template::parse("%{{a | trim | upper}} %{{b | upper | default(\"-\")}}");
+        assert_eq!(template.render(&values).unwrap(), "A -");
+        assert_eq!(template.render_strict(&values).unwrap(), "A -");
+    }
+
+    #[test]
+    fn test_dynamic_names() {
+        let values = [
+            ("env", "prod"),
+            ("db_url_prod", "postgres://prod"),
+            ("db_url_dev", "postgres://dev"),
+            ("ptr", "db_url_dev"),
+            ("dangling", "nope"),
+        ];
+        let cases = [
+            ("%{{db_url_%{{env}}}}", "postgres://prod"),
+            ("%{{db_url_%{{stage:-dev}}}}", "postgres://dev"),
+            (
+                "%{{%{{ptr}}}} %{{!ptr}} %{{!ptr | upper}}",
+                "postgres://dev postgres://dev POSTGRES://DEV",
+            ),
+            ("%{{!db_url_%{{!dangling:-ptr}}}}", ""),
+            (
+                "%{{!unset:-x}} %{{!dangling:-y}} %{{db_url_%{{unset}}:-z}}",
+                "x y z",
+            ),
+            (
+                "%{{db_url_%{{env}}_%{{env}}}} %%{{db_url_%{{env}}}}",
+                " %{{db_url_prod}}",
+            ),
+            ("%{{a%{{b%{{env}}}}}}", "%{{a}}"),
+        ];
+        for (template, expected) in cases {
+            let rendered = Template::parse(template).render(&values).unwrap();
+            assert_eq!(rendered, expected, "rendering {:?}", template);
+        }
+
+        let template = Template::parse("\n %{{db_url_%{{env}}:?}} %{{!env:?}}");
+        let names: Vec<_> = template.variables().into_iter().map(|v| v.name).collect();
+        assert_eq!(names, ["db_url_%{{env}}", "!env"]);
+        let err = template.render(&values).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "2:25: %{{!env}}: required variable is not set"
+        );
+        let errors = Template::parse("%{{a}}\n %{{db_url_%{{unset}}}}")
+            .render_strict(&values)
+            .unwrap_err();
+        assert_eq!(
+            errors.iter().last().unwrap().to_string(),
+            "2:2: failed to resolve %{{db_url_%{{unset}}}}: 2:12: variable %{{unset}} is not set"
+        );
+    }
+
+    #[test]
+    fn test_render_many_times() {
+        let template = Template::parse(include_str!("test_files/test_template.json.in"));
+        let expected_output = include_str!("test_files/test_output.json.in");
+        for _ in 0..3 {
+            let rendered = template
+                .render(&[("test_num", "1"), ("test_num_2", "2")])
+                .unwrap();
+            assert_eq!(rendered, expected_output);
+        }
+        let errors = template.render_strict(&[("unused", "")]).unwrap_err();
+        assert_eq!(errors.len(), 4);
+    }
+}
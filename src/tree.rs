@@ -0,0 +1,256 @@
+use crate::resolver::Resolver;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+/// Nested values for dotted and indexed placeholders such as
+/// `%{{host_config.log_config.max_size}}` or `%{{mounts[0].source}}`, so one
+/// hierarchical set of values can drive a whole configuration.
+///
+/// As a [`Resolver`], only scalars have a value, a path that ends at a map
+/// or a list is unset. Lists can be repeated over with `%{{#each list}}`.
+/// `serde_json::Value` and `serde_json::Map` (with the `serde_json` feature)
+/// and `yaml_rust2::Yaml` (with the `yaml` feature) resolve paths the same
+/// way.
+///
+/// Example usage:
+/// ```
+/// use str_var_subst::{replace_variables, Tree};
+/// let values = Tree::from_iter([
+///     ("name", Tree::from("databroker")),
+///     (
+///         "host_config",
+///         Tree::from_iter([("log_config", Tree::from_iter([("max_size", "10M")]))]),
+///     ),
+///     ("mounts", Tree::from(vec![Tree::from_iter([("source", "/var/run")])])),
+/// ]);
+/// let rendered = replace_variables(
+///     "%{{name}}: %{{host_config.log_config.max_size}} %{{mounts[0].source}}",
+///     &values,
+/// )
+/// .unwrap();
+/// assert_eq!(rendered, "databroker: 10M /var/run");
+/// ```
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tree {
+    Scalar(String),
+    List(Vec<Tree>),
+    Map(BTreeMap<String, Tree>),
+}
+
+impl Tree {
+    /// Follows a path such as `a.b[0].c` from this node
+    pub fn get(&self, path: &str) -> Option<&Tree> {
+        walk(path, |key| self.key(key))
+    }
+}
+
+impl From<&str> for Tree {
+    fn from(value: &str) -> Self {
+        Tree::Scalar(value.to_owned())
+    }
+}
+
+impl From<String> for Tree {
+    fn from(value: String) -> Self {
+        Tree::Scalar(value)
+    }
+}
+
+impl<T> From<Vec<T>> for Tree
+where
+    T: Into<Tree>,
+{
+    fn from(items: Vec<T>) -> Self {
+        Tree::List(items.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Builds a map
+impl<K, V> FromIterator<(K, V)> for Tree
+where
+    K: Into<String>,
+    V: Into<Tree>,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(entries: I) -> Self {
+        Tree::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .collect(),
+        )
+    }
+}
+
+impl Resolver for Tree {
+    fn resolve(&self, name: &str) -> Option<Cow<'_, str>> {
+        match self.get(name)? {
+            Tree::Scalar(value) => Some(Cow::Borrowed(value)),
+            _ => None,
+        }
+    }
+
+    fn list_len(&self, name: &str) -> Option<usize> {
+        match self.get(name)? {
+            Tree::List(items) => Some(items.len()),
+            _ => None,
+        }
+    }
+}
+
+/// A node of nested data that a path can walk through
+pub(crate) trait Node {
+    /// Entry of a map, `None` for other nodes
+    fn key(&self, key: &str) -> Option<&Self>;
+    /// Item of a list, `None` for other nodes
+    fn index(&self, index: usize) -> Option<&Self>;
+}
+
+impl Node for Tree {
+    fn key(&self, key: &str) -> Option<&Self> {
+        match self {
+            Tree::Map(entries) => entries.get(key),
+            _ => None,
+        }
+    }
+
+    fn index(&self, index: usize) -> Option<&Self> {
+        match self {
+            Tree::List(items) => items.get(index),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "serde_json")]
+impl Node for serde_json::Value {
+    fn key(&self, key: &str) -> Option<&Self> {
+        self.as_object()?.get(key)
+    }
+
+    fn index(&self, index: usize) -> Option<&Self> {
+        self.as_array()?.get(index)
+    }
+}
+
+#[cfg(feature = "yaml")]
+impl Node for yaml_rust2::Yaml {
+    fn key(&self, key: &str) -> Option<&Self> {
+        self.as_hash()?
+            .get(&yaml_rust2::Yaml::String(key.to_owned()))
+    }
+
+    fn index(&self, index: usize) -> Option<&Self> {
+        self.as_vec()?.get(index)
+    }
+}
+
+/// Follows a path such as `a.b[0].c`, where `root` looks up the entries of
+/// the top level. A top-level entry named like the whole path, e.g. `a.b`,
+/// takes precedence over the nested one.
+pub(crate) fn walk<'n, N, F>(path: &str, root: F) -> Option<&'n N>
+where
+    N: Node + ?Sized,
+    F: Fn(&str) -> Option<&'n N>,
+{
+    if let Some(node) = root(path) {
+        return Some(node);
+    }
+    let mut node: Option<&N> = None;
+    for part in path.split('.') {
+        let (key, mut indices) = part.split_at(part.find('[').unwrap_or(part.len()));
+        if key.is_empty() {
+            return None;
+        }
+        node = Some(match node {
+            Some(node) => node.key(key)?,
+            None => root(key)?,
+        });
+        while let Some(rest) = indices.strip_prefix('[') {
+            let (index, rest) = rest.split_once(']')?;
+            node = Some(node?.index(index.parse().ok()?)?);
+            indices = rest;
+        }
+        if !indices.is_empty() {
+            return None;
+        }
+    }
+    node
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::Template;
+
+    #[test]
+    fn test_tree_paths() {
+        let tree = Tree::from_iter([
+            ("a", Tree::from_iter([("b", Tree::from(vec!["x", "y"]))])),
+            ("a.b", Tree::from("flat")),
+            ("m", Tree::from(vec![Tree::from(vec!["00", "01"])])),
+        ]);
+        let cases = [
+            ("a.b[1]", Some("y")),
+            ("a.b", Some("flat")),
+            ("m[0][1]", Some("01")),
+            ("a", None),
+            ("a.b[2]", None),
+            ("a.b[x]", None),
+            ("a.b[0", None),
+            ("a.b[0]x", None),
+            ("a..b", None),
+            ("m.x", None),
+            ("m[0].x", None),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(tree.resolve(path).as_deref(), expected, "{}", path);
+        }
+        assert_eq!(tree.get("m[0]"), Some(&Tree::from(vec!["00", "01"])));
+
+        let template =
+            Template::parse("%{{a.b[0]}}-%{{m[0][1]:-z}}-%{{a.c:-z}} %{{a.}} %{{a[]}} %{{.a}}");
+        let names: Vec<_> = template.variables().into_iter().map(|v| v.name).collect();
+        assert_eq!(names, ["a.b[0]", "m[0][1]", "a.c"]);
+        assert_eq!(
+            template.render(&tree).unwrap(),
+            "x-01-z %{{a.}} %{{a[]}} %{{.a}}"
+        );
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_json_paths() {
+        let values = serde_json::json!({
+            "host_config": {"log_config": {"max_size": "10M", "max_files": 2}},
+            "mounts": [{"source": "/a"}, {"source": "/b"}]
+        });
+        let template = Template::parse(
+            "%{{host_config.log_config.max_size}} %{{host_config.log_config.max_files}} %{{mounts[1].source}} %{{mounts[0]}}",
+        );
+        let expected = r#"10M 2 /b {"source":"/a"}"#;
+        assert_eq!(template.render(&values).unwrap(), expected);
+        assert_eq!(
+            template.render(values.as_object().unwrap()).unwrap(),
+            expected
+        );
+        assert_eq!(
+            values.as_object().unwrap().resolve_value("mounts[0]"),
+            Some(serde_json::json!({"source": "/a"}))
+        );
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_yaml_paths() {
+        let values = yaml_rust2::YamlLoader::load_from_str(
+            "host_config:\n  log_config: {max_size: 10M, max_files: 2, compress: true}\nmounts:\n  - source: /a\nempty: ~\n",
+        )
+        .unwrap()
+        .remove(0);
+        let template = Template::parse(
+            "%{{host_config.log_config.max_size}} %{{host_config.log_config.max_files}} %{{host_config.log_config.compress}} %{{mounts[0].source}} %{{empty:-none}} %{{mounts:-list}}",
+        );
+        assert_eq!(template.render(&values).unwrap(), "10M 2 true /a none list");
+    }
+}
@@ -0,0 +1,168 @@
+use crate::error::Error;
+use crate::resolver::Resolver;
+use crate::template::{Segment, Settings, Template};
+use serde_json::Value;
+
+/// Replaces %{{variable}} placeholders in the strings (and object keys) of a
+/// parsed JSON document.
+///
+/// A string that consists of a single placeholder, e.g. `"%{{max_files}}"`,
+/// is replaced by the typed value from [`Resolver::resolve_value`] (a number,
+/// boolean, null, array or object), so resolvers such as `serde_json::Map`
+/// can supply `2` instead of `"2"`. If the value is a string, or the variable
+/// is unset and the placeholder's operator applies, or the placeholder has
+/// filters, the result is a string.
+/// Placeholders embedded in longer strings are interpolated as text, with the
+/// same semantics as [`replace_variables`](crate::replace_variables).
+///
+/// The line and column of an error are relative to the JSON string that
+/// contains the placeholder.
+///
+/// Example usage:
+/// ```
+/// use serde_json::json;
+/// use str_var_subst::replace_variables_value;
+/// let template = json!({
+///     "max_files": "%{{max_files}}",
+///     "privileged": "%{{privileged:-false}}",
+///     "log": "/var/log/%{{name}}-%{{max_files}}.log"
+/// });
+/// let values = json!({"max_files": 2, "name": "databroker"});
+/// let rendered = replace_variables_value(&template, values.as_object().unwrap()).unwrap();
+/// assert_eq!(
+///     rendered,
+///     json!({
+///         "max_files": 2,
+///         "privileged": "false",
+///         "log": "/var/log/databroker-2.log"
+///     })
+/// );
+/// ```
+///
+pub fn replace_variables_value<R>(value: &Value, resolver: &R) -> Result<Value, Error>
+where
+    R: Resolver + ?Sized,
+{
+    match value {
+        Value::String(text) => replace_in_string(text, resolver),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| replace_variables_value(item, resolver))
+            .collect::<Result<_, _>>()
+            .map(Value::Array),
+        Value::Object(object) => object
+            .iter()
+            .map(|(key, value)| {
+                let key = Template::parse(key).render(resolver)?;
+                Ok((key, replace_variables_value(value, resolver)?))
+            })
+            .collect::<Result<_, _>>()
+            .map(Value::Object),
+        value => Ok(value.clone()),
+    }
+}
+
+fn replace_in_string<R>(text: &str, resolver: &R) -> Result<Value, Error>
+where
+    R: Resolver + ?Sized,
+{
+    let template = Template::parse(text);
+    if let [Segment::Placeholder(placeholder)] = template.segments() {
+        let name = placeholder
+            .variable_name(&|var| Ok(resolver.resolve(var)), Settings::default())
+            .ok()
+            .flatten();
+        match name.and_then(|name| resolver.resolve_value(&name)) {
+            None | Some(Value::String(_)) => {}
+            Some(_) if !placeholder.filters.is_empty() => {}
+            Some(value) => return Ok(value),
+        }
+    }
+    template.render(resolver).map(Value::String)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::Layered;
+    use serde_json::json;
+
+    #[test]
+    fn test_typed_placeholders() {
+        let values = json!({
+            "max_files": 2,
+            "privileged": false,
+            "resources": null,
+            "mounts": ["/a", "/b"],
+            "empty": "",
+            "name": "databroker",
+            "pointer": "max_files"
+        });
+        let values = values.as_object().unwrap();
+        let template = json!({
+            "%{{name}}": {
+                "max_files": "%{{max_files}}",
+                "privileged": "%{{privileged}}",
+                "resources": "%{{resources:-none}}",
+                "mounts": ["%{{mounts}}", "%{{mounts}}/%{{max_files}}"],
+                "empty": "%{{empty:-default}}",
+                "unset": "%{{unset}}",
+                "escaped": "%%{{max_files}}",
+                "filtered": "%{{privileged | upper}}",
+                "indirect": "%{{!pointer}}",
+                "number": 1
+            }
+        });
+        assert_eq!(
+            replace_variables_value(&template, values).unwrap(),
+            json!({
+                "databroker": {
+                    "max_files": 2,
+                    "privileged": false,
+                    "resources": null,
+                    "mounts": [["/a", "/b"], "[\"/a\",\"/b\"]/2"],
+                    "empty": "default",
+                    "unset": "",
+                    "escaped": "%{{max_files}}",
+                    "filtered": "FALSE",
+                    "indirect": 2,
+                    "number": 1
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_typed_layers_and_errors() {
+        let typed = json!({"max_files": 2});
+        let resolver = Layered::new()
+            .layer("cli", [("max_files", "3")])
+            .layer("json", typed.as_object().unwrap().clone());
+        let template = json!(["%{{max_files}}", "%{{max_files:?}}"]);
+        assert_eq!(
+            replace_variables_value(&template, &resolver).unwrap(),
+            json!(["3", "3"])
+        );
+        let resolver = Layered::new().layer("json", typed.as_object().unwrap().clone());
+        assert_eq!(
+            replace_variables_value(&template, &resolver).unwrap(),
+            json!([2, 2])
+        );
+
+        let err =
+            replace_variables_value(&json!({"a": "x %{{b:?b is needed}}"}), &resolver).unwrap_err();
+        assert_eq!(err.name(), "b");
+        assert_eq!(err.column(), 3);
+    }
+
+    #[test]
+    fn test_typed_json_template() {
+        let template: Value =
+            serde_json::from_str(include_str!("test_files/test_template.json.in")).unwrap();
+        let values = json!({"test_num": 1, "test_num_2": "2"});
+        let rendered = replace_variables_value(&template, values.as_object().unwrap()).unwrap();
+        assert_eq!(rendered["domain_name"], json!(1));
+        assert_eq!(rendered["hosts_path"], json!(""));
+        assert_eq!(rendered["hostname_path"], json!("2"));
+    }
+}
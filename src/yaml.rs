@@ -0,0 +1,652 @@
+use crate::error::{ErrorKind, Errors, FormatError};
+use crate::json::{escape_json, json_string};
+use crate::resolver::Resolver;
+use crate::template::{Segment, Settings, Template};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::borrow::Cow;
+use yaml_rust2::YamlLoader;
+
+lazy_static! {
+    /// Values that YAML 1.1 or 1.2 may read as something else than a string
+    static ref SPECIAL_RE: Regex = Regex::new(
+        r"^(?:[-+]?\.?[0-9]|[-+]?\.(?:inf|Inf|INF)$|\.(?:nan|NaN|NAN)$|(?:y|Y|yes|Yes|YES|n|N|no|No|NO|true|True|TRUE|false|False|FALSE|on|On|ON|off|Off|OFF|null|Null|NULL|~|<<|=)$)"
+    )
+    .unwrap();
+    /// Values that are inserted as they are in a plain scalar when rendering
+    /// with types, as in JSON
+    static ref JSON_SCALAR_RE: Regex =
+        Regex::new(r"^(?:true|false|null|-?(?:0|[1-9][0-9]*)(?:\.[0-9]+)?)$").unwrap();
+}
+
+/// Characters that have a special meaning at the start of a plain scalar
+const INDICATORS: &[char] = &[
+    '-', '?', ':', ',', '[', ']', '{', '}', '#', '&', '*', '!', '|', '>', '\'', '"', '%', '@', '`',
+];
+
+/// Characters that end a plain scalar in a flow collection
+const FLOW_INDICATORS: &[char] = &[',', '[', ']', '{', '}'];
+
+/// Same as [`replace_variables`](crate::replace_variables) but for YAML
+/// templates. Every value is inserted so that it is read back as the same
+/// string, based on the scalar the placeholder is part of:
+/// - in a double-quoted scalar `"`, `\` and control characters are escaped
+/// - in a single-quoted scalar `'` is doubled
+/// - a placeholder that makes up a whole plain (unquoted) scalar is
+///   double-quoted if the value would otherwise be read as a boolean, a
+///   number, null or as YAML syntax, e.g. `2`, `true`, `yes`, `0755` or
+///   `foo: bar`. See [`replace_variables_yaml_typed`] to fill in numbers and
+///   booleans.
+/// - in a block scalar (`|` or `>`) the lines of the value are indented
+///   like the line of the placeholder. A value whose first non-empty line
+///   starts with whitespace is refused, it would change the indentation of
+///   the block.
+///
+/// Values that cannot be inserted safely fail with [`ErrorKind::Escape`],
+/// e.g. a value with `: ` in the middle of a plain scalar or a line break in
+/// a comment. Fails with [`FormatError::InvalidOutput`] if the result is not
+/// valid YAML.
+///
+/// Example usage:
+/// ```
+/// use str_var_subst::replace_variables_yaml;
+/// let template = "mode: %{{mode}}\nreplicas: %{{replicas}}\ncmd: \"%{{cmd}}\"\nscript: |\n  %{{script}}\n";
+/// let values = [
+///     ("mode", "0755"),
+///     ("replicas", "2"),
+///     ("cmd", "echo \"hi\""),
+///     ("script", "set -e\nmake"),
+/// ];
+/// let rendered = replace_variables_yaml(template, &values).unwrap();
+/// assert_eq!(
+///     rendered,
+///     "mode: \"0755\"\nreplicas: \"2\"\ncmd: \"echo \\\"hi\\\"\"\nscript: |\n  set -e\n  make\n"
+/// );
+/// ```
+///
+pub fn replace_variables_yaml<R>(template_text: &str, resolver: &R) -> Result<String, FormatError>
+where
+    R: Resolver + ?Sized,
+{
+    Template::parse(template_text).render_yaml(resolver)
+}
+
+/// Same as [`replace_variables_yaml`] but a placeholder that makes up a whole
+/// plain scalar is inserted as it is if the value is `true`, `false`, `null`
+/// or a decimal number such as `2` or `-1.5`, as in JSON, so it is read back
+/// as a boolean, null or a number. Other values are quoted as needed.
+///
+/// Example usage:
+/// ```
+/// use str_var_subst::replace_variables_yaml_typed;
+/// let template = "replicas: %{{replicas}}\nprivileged: %{{privileged}}\nmode: %{{mode}}\n";
+/// let values = [("replicas", "2"), ("privileged", "false"), ("mode", "0755")];
+/// let rendered = replace_variables_yaml_typed(template, &values).unwrap();
+/// assert_eq!(rendered, "replicas: 2\nprivileged: false\nmode: \"0755\"\n");
+/// ```
+///
+pub fn replace_variables_yaml_typed<R>(
+    template_text: &str,
+    resolver: &R,
+) -> Result<String, FormatError>
+where
+    R: Resolver + ?Sized,
+{
+    Template::parse(template_text).render_yaml_typed(resolver)
+}
+
+impl Template {
+    /// Renders a YAML template, see [`replace_variables_yaml`]
+    pub fn render_yaml<R>(&self, resolver: &R) -> Result<String, FormatError>
+    where
+        R: Resolver + ?Sized,
+    {
+        self.render_yaml_with(resolver, false)
+    }
+
+    /// Renders a YAML template with numbers and booleans inserted as they
+    /// are, see [`replace_variables_yaml_typed`]
+    pub fn render_yaml_typed<R>(&self, resolver: &R) -> Result<String, FormatError>
+    where
+        R: Resolver + ?Sized,
+    {
+        self.render_yaml_with(resolver, true)
+    }
+
+    fn render_yaml_with<R>(&self, resolver: &R, typed: bool) -> Result<String, FormatError>
+    where
+        R: Resolver + ?Sized,
+    {
+        let contexts = scalar_contexts(self);
+        let settings = Settings::for_resolver(&resolver);
+        let rendered = self
+            .render_with_escape(
+                |var| Ok(resolver.resolve(var)),
+                |index, value| contexts[index].escape(value, typed),
+                settings,
+            )
+            .map_err(Errors::into_first)?;
+
+        if let Err(err) = YamlLoader::load_from_str(&rendered) {
+            return Err(FormatError::InvalidOutput {
+                format: "YAML",
+                message: err.info().to_owned(),
+                line: err.marker().line(),
+                column: err.marker().col() + 1,
+            });
+        }
+        Ok(rendered)
+    }
+}
+
+/// The kind of scalar a placeholder is part of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Context {
+    DoubleQuoted,
+    SingleQuoted,
+    /// The placeholder is the whole plain scalar
+    Plain {
+        flow: bool,
+    },
+    /// The placeholder is part of a longer plain scalar, `first` if the
+    /// scalar starts with it
+    EmbeddedPlain {
+        first: bool,
+        flow: bool,
+    },
+    /// Content of a `|` or `>` block scalar, on a line with `indent` spaces
+    Block {
+        indent: usize,
+    },
+    Comment,
+}
+
+impl Context {
+    /// Escapes the value for the scalar, with `typed` JSON scalars such as
+    /// `2` or `true` are not quoted in a plain scalar
+    fn escape<'v>(&self, value: &'v str, typed: bool) -> Result<Cow<'v, str>, ErrorKind> {
+        let has_control = value.chars().any(|c| c.is_control() && c != '\t');
+        match *self {
+            Context::DoubleQuoted => Ok(escape_json(value)),
+            Context::SingleQuoted if has_control => Err(ErrorKind::Escape(String::from(
+                "line breaks and control characters cannot be written in a single-quoted scalar",
+            ))),
+            Context::SingleQuoted => Ok(match value.contains('\'') {
+                true => Cow::Owned(value.replace('\'', "''")),
+                false => Cow::Borrowed(value),
+            }),
+            Context::Plain { flow } => {
+                if (typed && JSON_SCALAR_RE.is_match(value)) || is_plain_string(value, flow) {
+                    Ok(Cow::Borrowed(value))
+                } else {
+                    Ok(Cow::Owned(json_string(value)))
+                }
+            }
+            Context::EmbeddedPlain { first, flow } => {
+                let unsafe_start = first && value.starts_with(INDICATORS);
+                if value.is_empty() {
+                    Ok(Cow::Borrowed(value))
+                } else if has_control
+                    || unsafe_start
+                    || !is_plain_fragment(value)
+                    || (flow && value.contains(FLOW_INDICATORS))
+                {
+                    Err(ErrorKind::Escape(String::from(
+                        "the value would change the meaning of the unquoted scalar, quote it in the template",
+                    )))
+                } else {
+                    Ok(Cow::Borrowed(value))
+                }
+            }
+            // the first line that is not empty sets the indentation of the block
+            Context::Block { .. }
+                if value
+                    .split('\n')
+                    .find(|line| !line.is_empty())
+                    .is_some_and(|line| line.starts_with([' ', '\t'])) =>
+            {
+                Err(ErrorKind::Escape(String::from(
+                    "leading whitespace would be read as the indentation of the block scalar",
+                )))
+            }
+            Context::Block { indent } if value.contains('\n') => {
+                let mut indented = String::with_capacity(value.len() + indent * 4);
+                for (i, line) in value.split('\n').enumerate() {
+                    if i > 0 {
+                        indented.push('\n');
+                        if !line.is_empty() {
+                            indented.push_str(&" ".repeat(indent));
+                        }
+                    }
+                    indented.push_str(line);
+                }
+                Ok(Cow::Owned(indented))
+            }
+            Context::Block { .. } => Ok(Cow::Borrowed(value)),
+            Context::Comment if value.contains(['\n', '\r']) => Err(ErrorKind::Escape(
+                String::from("line breaks cannot be written in a comment"),
+            )),
+            Context::Comment => Ok(Cow::Borrowed(value)),
+        }
+    }
+}
+
+/// Whether the value is read back as the same string from a plain scalar
+fn is_plain_string(value: &str, flow: bool) -> bool {
+    let ambiguous = value.is_empty()
+        || value.starts_with(INDICATORS)
+        || SPECIAL_RE.is_match(value)
+        || value.chars().any(char::is_control)
+        || (flow && value.contains(FLOW_INDICATORS));
+    !ambiguous && is_plain_fragment(value)
+}
+
+/// Whether the value can be part of a plain scalar without ending it or
+/// starting a comment
+fn is_plain_fragment(value: &str) -> bool {
+    value.trim() == value
+        && !value.starts_with('#')
+        && !value.ends_with(':')
+        && !value.contains(": ")
+        && !value.contains(":\t")
+        && !value.contains(" #")
+        && !value.contains("\t#")
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Item {
+    Char(char),
+    Placeholder,
+}
+
+#[derive(PartialEq, Eq)]
+enum State {
+    /// Between tokens, e.g. after `key: ` or `- `
+    Between,
+    Plain,
+    DoubleQuoted {
+        escaped: bool,
+    },
+    SingleQuoted,
+    /// Anchor, alias or tag
+    Property,
+    Comment,
+}
+
+/// A plain scalar being scanned: the indexes of its placeholders, whether it
+/// starts with a placeholder and whether it has any other text
+struct PlainScalar {
+    placeholders: Vec<usize>,
+    starts_with_placeholder: bool,
+    has_text: bool,
+    flow: bool,
+}
+
+impl PlainScalar {
+    fn finish(self, contexts: &mut [Context]) {
+        let whole = self.placeholders.len() == 1 && !self.has_text;
+        for (i, &index) in self.placeholders.iter().enumerate() {
+            contexts[index] = match whole {
+                true => Context::Plain { flow: self.flow },
+                false => Context::EmbeddedPlain {
+                    first: i == 0 && self.starts_with_placeholder,
+                    flow: self.flow,
+                },
+            };
+        }
+    }
+}
+
+/// The characters and placeholders of the template. The parts of a
+/// `%{{#if variable}}` section follow each other, which fits sections of
+/// whole lines.
+fn flatten(template: &Template, items: &mut Vec<Item>) {
+    for segment in template.segments() {
+        match segment {
+            Segment::Literal(text) => items.extend(text.chars().map(Item::Char)),
+            Segment::Placeholder(_) => items.push(Item::Placeholder),
+            Segment::Section(section) => {
+                flatten(&section.body, items);
+                flatten(&section.otherwise, items);
+            }
+        }
+    }
+}
+
+/// For every placeholder of the template, the kind of scalar it is part of.
+/// Only the literal text of the template is taken into account. This is a
+/// line-based approximation of the YAML syntax that covers block and flow
+/// collections, quoted, plain and block scalars and comments.
+fn scalar_contexts(template: &Template) -> Vec<Context> {
+    let mut items = Vec::new();
+    flatten(template, &mut items);
+
+    let mut contexts = Vec::new();
+    let mut state = State::Between;
+    let mut scalar: Option<PlainScalar> = None;
+    let mut flow_depth = 0usize;
+    // Indentation of the node that owns the current block scalar
+    let mut block_parent: Option<usize> = None;
+    let mut i = 0;
+    while i < items.len() {
+        // At the start of a line
+        let indent = items[i..]
+            .iter()
+            .take_while(|item| **item == Item::Char(' '))
+            .count();
+        let blank = matches!(items.get(i + indent), None | Some(Item::Char('\n' | '\r')));
+        if let Some(parent) = block_parent {
+            if blank || indent > parent {
+                while i < items.len() && items[i] != Item::Char('\n') {
+                    if items[i] == Item::Placeholder {
+                        contexts.push(Context::Block { indent });
+                    }
+                    i += 1;
+                }
+                i += 1;
+                continue;
+            }
+            block_parent = None;
+        }
+
+        // Column of the first token of the line, after `- ` indicators
+        let mut node_indent = None;
+        let mut column = 0;
+        while i < items.len() {
+            let item = items[i];
+            let next = items.get(i + 1).copied();
+            let next_is_space = matches!(next, None | Some(Item::Char(' ' | '\t' | '\n' | '\r')));
+            i += 1;
+            column += 1;
+            if item == Item::Char('\n') {
+                break;
+            }
+            if item == Item::Placeholder && state != State::Plain && state != State::Between {
+                contexts.push(match state {
+                    State::DoubleQuoted { .. } => Context::DoubleQuoted,
+                    State::SingleQuoted => Context::SingleQuoted,
+                    State::Comment => Context::Comment,
+                    _ => Context::EmbeddedPlain {
+                        first: false,
+                        flow: flow_depth > 0,
+                    },
+                });
+                continue;
+            }
+            let c = match item {
+                Item::Char(c) => c,
+                Item::Placeholder => '\0',
+            };
+            match state {
+                State::DoubleQuoted { escaped } => {
+                    state = match c {
+                        _ if escaped => State::DoubleQuoted { escaped: false },
+                        '\\' => State::DoubleQuoted { escaped: true },
+                        '"' => State::Between,
+                        _ => State::DoubleQuoted { escaped: false },
+                    };
+                    continue;
+                }
+                State::SingleQuoted => {
+                    if c == '\'' {
+                        if next == Some(Item::Char('\'')) {
+                            i += 1;
+                            column += 1;
+                        } else {
+                            state = State::Between;
+                        }
+                    }
+                    continue;
+                }
+                State::Comment => continue,
+                State::Property => {
+                    if c == ' ' || c == '\t' {
+                        state = State::Between;
+                    }
+                    continue;
+                }
+                State::Plain => {
+                    let ends = match c {
+                        ':' => next_is_space,
+                        '#' => matches!(items.get(i.wrapping_sub(2)), Some(Item::Char(' ' | '\t'))),
+                        c => flow_depth > 0 && FLOW_INDICATORS.contains(&c),
+                    };
+                    if !ends {
+                        let scalar = scalar.as_mut().unwrap();
+                        match item {
+                            Item::Placeholder => scalar.placeholders.push(contexts.len()),
+                            Item::Char(' ' | '\t') => {}
+                            Item::Char(_) => scalar.has_text = true,
+                        }
+                        if item == Item::Placeholder {
+                            contexts.push(Context::Plain { flow: false });
+                        }
+                        continue;
+                    }
+                    scalar.take().unwrap().finish(&mut contexts);
+                    state = State::Between;
+                }
+                State::Between => {}
+            }
+
+            // Between tokens
+            match item {
+                Item::Char(' ' | '\t' | '\r') => {}
+                Item::Char('#') => state = State::Comment,
+                Item::Char('-' | '?' | ':') if next_is_space => {}
+                Item::Char('[' | '{') => flow_depth += 1,
+                Item::Char(']' | '}') => flow_depth = flow_depth.saturating_sub(1),
+                Item::Char(',') if flow_depth > 0 => {}
+                Item::Char('|' | '>') if flow_depth == 0 && is_block_header(&items[i..]) => {
+                    block_parent = Some(node_indent.unwrap_or(indent));
+                    state = State::Comment;
+                }
+                _ => {
+                    node_indent.get_or_insert(column - 1);
+                    match item {
+                        Item::Char('"') => state = State::DoubleQuoted { escaped: false },
+                        Item::Char('\'') => state = State::SingleQuoted,
+                        Item::Char('&' | '*' | '!') => state = State::Property,
+                        item => {
+                            let mut plain = PlainScalar {
+                                placeholders: Vec::new(),
+                                starts_with_placeholder: item == Item::Placeholder,
+                                has_text: item != Item::Placeholder,
+                                flow: flow_depth > 0,
+                            };
+                            if item == Item::Placeholder {
+                                plain.placeholders.push(contexts.len());
+                                contexts.push(Context::Plain { flow: false });
+                            }
+                            scalar = Some(plain);
+                            state = State::Plain;
+                        }
+                    }
+                }
+            }
+        }
+
+        // End of the line
+        if let Some(scalar) = scalar.take() {
+            scalar.finish(&mut contexts);
+        }
+        if matches!(state, State::Plain | State::Comment | State::Property) {
+            state = State::Between;
+        }
+    }
+    if let Some(scalar) = scalar.take() {
+        scalar.finish(&mut contexts);
+    }
+    contexts
+}
+
+/// Whether the rest of the line after `|` or `>` is a block scalar header:
+/// optional indentation and chomping indicators and a comment
+fn is_block_header(rest: &[Item]) -> bool {
+    let mut rest = rest
+        .iter()
+        .skip_while(|item| matches!(item, Item::Char('+' | '-' | '0'..='9')))
+        .skip_while(|item| matches!(item, Item::Char(' ' | '\t')));
+    matches!(rest.next(), None | Some(Item::Char('\n' | '\r' | '#')))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contexts(template_text: &str) -> Vec<Context> {
+        scalar_contexts(&Template::parse(template_text))
+    }
+
+    #[test]
+    fn test_yaml_contexts() {
+        let template = "\
+a: %{{v}} # %{{v}}
+b: \"x %{{v}} \\\" %{{v}}\"
+c: 'it''s %{{v}}'
+- %{{v}}/%{{v}}
+- [%{{v}}, x %{{v}}]
+d: &anchor %{{v}}
+e: |-
+  %{{v}}
+    %{{v}}
+
+f: %{{v}}
+";
+        assert_eq!(
+            contexts(template),
+            [
+                Context::Plain { flow: false },
+                Context::Comment,
+                Context::DoubleQuoted,
+                Context::DoubleQuoted,
+                Context::SingleQuoted,
+                Context::EmbeddedPlain {
+                    first: true,
+                    flow: false
+                },
+                Context::EmbeddedPlain {
+                    first: false,
+                    flow: false
+                },
+                Context::Plain { flow: true },
+                Context::EmbeddedPlain {
+                    first: false,
+                    flow: true
+                },
+                Context::Plain { flow: false },
+                Context::Block { indent: 2 },
+                Context::Block { indent: 4 },
+                Context::Plain { flow: false },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_yaml_plain_values() {
+        let values = [
+            ("yes", "\"yes\""),
+            ("0755", "\"0755\""),
+            ("foo: bar", "\"foo: bar\""),
+            ("", "\"\""),
+            ("~", "\"~\""),
+            ("1e3", "\"1e3\""),
+            (".inf", "\".inf\""),
+            ("2001-12-14", "\"2001-12-14\""),
+            ("- item", "\"- item\""),
+            ("a #b", "\"a #b\""),
+            ("line\nbreak", "\"line\\nbreak\""),
+            (" padded", "\" padded\""),
+            ("2", "\"2\""),
+            ("-1.5", "\"-1.5\""),
+            ("false", "\"false\""),
+            ("null", "\"null\""),
+            ("databroker:0.2.5", "databroker:0.2.5"),
+            ("a b", "a b"),
+        ];
+        for (value, expected) in values {
+            let rendered = replace_variables_yaml("key: %{{v}}\n", &[("v", value)]).unwrap();
+            assert_eq!(rendered, format!("key: {}\n", expected), "{:?}", value);
+        }
+
+        let typed = [
+            ("2", "2"),
+            ("-1.5", "-1.5"),
+            ("false", "false"),
+            ("null", "null"),
+            ("yes", "\"yes\""),
+            ("0755", "\"0755\""),
+            ("1e3", "\"1e3\""),
+        ];
+        for (value, expected) in typed {
+            let rendered = replace_variables_yaml_typed("key: %{{v}}\n", &[("v", value)]).unwrap();
+            assert_eq!(rendered, format!("key: {}\n", expected), "{:?}", value);
+        }
+        let rendered = replace_variables_yaml_typed("key: \"%{{v}}\"\n", &[("v", "2")]).unwrap();
+        assert_eq!(rendered, "key: \"2\"\n");
+        let rendered = replace_variables_yaml("[%{{v}}]", &[("v", "a,b")]).unwrap();
+        assert_eq!(rendered, "[\"a,b\"]");
+    }
+
+    #[test]
+    fn test_yaml_escaping() {
+        let template = "\
+image: ghcr.io/%{{image}}:%{{tag:-latest}}
+cmd: \"%{{cmd}}\"
+name: '%{{name}}'
+script: >
+  %{{script}}
+  done
+";
+        let values = [
+            ("image", "eclipse/kuksa"),
+            ("cmd", "echo \"a\\b\"\n"),
+            ("name", "it's"),
+            ("script", "a\n\nb"),
+        ];
+        assert_eq!(
+            replace_variables_yaml(template, &values).unwrap(),
+            "\
+image: ghcr.io/eclipse/kuksa:latest
+cmd: \"echo \\\"a\\\\b\\\"\\n\"
+name: 'it''s'
+script: >
+  a
+
+  b
+  done
+"
+        );
+
+        let err = replace_variables_yaml("a: x%{{v}}", &[("v", "y: z")]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "1:5: cannot insert the value of %{{v}}: the value would change the meaning of the unquoted scalar, quote it in the template"
+        );
+        let err = replace_variables_yaml("s: |\n  %{{v}}\n", &[("v", "  indented")]).unwrap_err();
+        assert!(
+            matches!(&err, FormatError::Substitution(err) if matches!(err.kind(), ErrorKind::Escape(_)))
+        );
+        let err = replace_variables_yaml("s: >\n  %{{v}}\n", &[("v", "\n\tx")]).unwrap_err();
+        assert!(matches!(err, FormatError::Substitution(_)));
+        let rendered = replace_variables_yaml("s: |\n  %{{v}}\n", &[("v", "a\n  b")]).unwrap();
+        assert_eq!(rendered, "s: |\n  a\n    b\n");
+        let err = replace_variables_yaml("a: 1 # %{{v}}", &[("v", "\nb: 2")]).unwrap_err();
+        assert!(matches!(err, FormatError::Substitution(_)));
+        let err = replace_variables_yaml("a: '%{{v}}'", &[("v", "\n")]).unwrap_err();
+        assert!(matches!(err, FormatError::Substitution(_)));
+    }
+
+    #[test]
+    fn test_yaml_invalid_output() {
+        let err = replace_variables_yaml("a: [%{{v}}\nb: 1\n", &[("v", "1")]).unwrap_err();
+        match err {
+            FormatError::InvalidOutput { format, line, .. } => {
+                assert_eq!(format, "YAML");
+                assert_eq!(line, 2);
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+        assert!(replace_variables_yaml("a: 1\n---\nb: %{{v}}\n", &[("v", "2")]).is_ok());
+    }
+}
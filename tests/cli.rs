@@ -0,0 +1,266 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn str_var_subst(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_str-var-subst"))
+        .args(args)
+        .env("STR_VAR_SUBST_CLI_SET", "from env")
+        .env_remove("STR_VAR_SUBST_CLI_UNSET")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("str-var-subst-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_stdin_to_stdout() {
+    let out = str_var_subst(
+        &[],
+        "%{{STR_VAR_SUBST_CLI_SET}} [%{{STR_VAR_SUBST_CLI_UNSET}}]",
+    );
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "from env []");
+}
+
+#[test]
+fn test_strict_lists_every_unset_variable() {
+    let out = str_var_subst(
+        &["--strict"],
+        "%{{STR_VAR_SUBST_CLI_UNSET}}\n%{{STR_VAR_SUBST_CLI_UNSET_2}}%{{STR_VAR_SUBST_CLI_SET}}",
+    );
+    assert_eq!(out.status.code(), Some(1));
+    assert!(out.stdout.is_empty());
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("1:1: variable %{{STR_VAR_SUBST_CLI_UNSET}} is not set"));
+    assert!(stderr.contains("2:1: variable %{{STR_VAR_SUBST_CLI_UNSET_2}} is not set"));
+}
+
+#[test]
+fn test_vars_file_and_output() {
+    let dir = temp_dir("vars-file");
+    let vars_file = dir.join("vars.env");
+    let template = dir.join("template.json.in");
+    let output = dir.join("template.json");
+    fs::write(
+        &vars_file,
+        "# comment\nexport STR_VAR_SUBST_CLI_SET=\"from file\"\nname = John\n",
+    )
+    .unwrap();
+    fs::write(
+        &template,
+        r#"{"a": "%{{STR_VAR_SUBST_CLI_SET}}", "b": "%{{name}}"}"#,
+    )
+    .unwrap();
+
+    let out = str_var_subst(
+        &[
+            "--vars-file",
+            vars_file.to_str().unwrap(),
+            template.to_str().unwrap(),
+            "--output",
+            output.to_str().unwrap(),
+        ],
+        "",
+    );
+    assert!(out.status.success(), "{:?}", out);
+    assert!(out.stdout.is_empty());
+    assert_eq!(
+        fs::read_to_string(&output).unwrap(),
+        r#"{"a": "from file", "b": "John"}"#
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_shell_syntax() {
+    let template =
+        "$STR_VAR_SUBST_CLI_SET|${STR_VAR_SUBST_CLI_UNSET:-default}|$HOME|%{{STR_VAR_SUBST_CLI_SET}}";
+    let out = str_var_subst(
+        &[
+            "--syntax",
+            "shell",
+            "--shell-format",
+            "$STR_VAR_SUBST_CLI_SET ${STR_VAR_SUBST_CLI_UNSET}",
+        ],
+        template,
+    );
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "from env|default|$HOME|%{{STR_VAR_SUBST_CLI_SET}}"
+    );
+
+    let out = str_var_subst(&["--syntax=both", "--strict"], "${STR_VAR_SUBST_CLI_UNSET}");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8(out.stderr)
+        .unwrap()
+        .contains("1:1: variable ${STR_VAR_SUBST_CLI_UNSET} is not set"));
+    let out = str_var_subst(
+        &["--check", "--syntax", "shell"],
+        "$STR_VAR_SUBST_CLI_SET ${STR_VAR_SUBST_CLI_UNSET:?needed}",
+    );
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "<stdin>:\n  1:1: $STR_VAR_SUBST_CLI_SET resolved from environment\n  1:24: ${STR_VAR_SUBST_CLI_UNSET} is missing: needed\n"
+    );
+    let out = str_var_subst(
+        &["--syntax=both"],
+        "$STR_VAR_SUBST_CLI_SET %{{STR_VAR_SUBST_CLI_SET}}",
+    );
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "from env from env");
+    let out = str_var_subst(&["--syntax", "dollar"], "");
+    assert_eq!(out.status.code(), Some(2));
+}
+
+#[test]
+fn test_exit_codes() {
+    let out = str_var_subst(&["--no-such-flag"], "");
+    assert_eq!(out.status.code(), Some(2));
+    let out = str_var_subst(&["/nonexistent/template.in"], "");
+    assert_eq!(out.status.code(), Some(3));
+    let out = str_var_subst(&[], "%{{STR_VAR_SUBST_CLI_UNSET:?must be set}}");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8(out.stderr)
+        .unwrap()
+        .contains("must be set"));
+}
+
+#[test]
+fn test_recursive_rendering() {
+    let dir = temp_dir("recursive");
+    fs::create_dir_all(dir.join("sub/deeper")).unwrap();
+    fs::write(dir.join("a.json.in"), "a=%{{STR_VAR_SUBST_CLI_SET}}").unwrap();
+    fs::write(
+        dir.join("sub/b.yaml.in"),
+        "b=%{{STR_VAR_SUBST_CLI_UNSET:-default}}",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("sub/deeper/c.in"),
+        "c=%{{STR_VAR_SUBST_CLI_UNSET:?c needs it}}",
+    )
+    .unwrap();
+    fs::write(dir.join("notes.txt"), "%{{STR_VAR_SUBST_CLI_SET}}").unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir.join("a.json.in"), fs::Permissions::from_mode(0o750)).unwrap();
+    }
+
+    let out = str_var_subst(&["--recursive", dir.to_str().unwrap()], "");
+    assert_eq!(out.status.code(), Some(1));
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.contains("a.json.in -> "));
+    assert!(stdout.contains("failed   "));
+    assert!(stdout.ends_with("2 template(s) rendered, 1 failed\n"));
+    assert!(String::from_utf8(out.stderr)
+        .unwrap()
+        .contains("c needs it"));
+
+    assert_eq!(
+        fs::read_to_string(dir.join("a.json")).unwrap(),
+        "a=from env"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("sub/b.yaml")).unwrap(),
+        "b=default"
+    );
+    assert!(!dir.join("sub/deeper/c").exists());
+    assert!(!dir.join("notes").exists());
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(dir.join("a.json"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o750);
+    }
+    let leftovers: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().ends_with(".tmp"))
+        .collect();
+    assert!(leftovers.is_empty());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_in_place() {
+    let dir = temp_dir("in-place");
+    let template = dir.join("config.json");
+    fs::write(&template, "%{{STR_VAR_SUBST_CLI_SET}}").unwrap();
+    let out = str_var_subst(&["--in-place", template.to_str().unwrap()], "");
+    assert!(out.status.success(), "{:?}", out);
+    assert_eq!(fs::read_to_string(&template).unwrap(), "from env");
+
+    let out = str_var_subst(&["--in-place"], "");
+    assert_eq!(out.status.code(), Some(2));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_check_reports() {
+    let dir = temp_dir("check");
+    let vars_file = dir.join("vars.env");
+    fs::write(&vars_file, "name=John\n").unwrap();
+    let template =
+        "%{{name}} %{{STR_VAR_SUBST_CLI_SET}} %{{tag:-latest}}\n%{{STR_VAR_SUBST_CLI_UNSET}}";
+
+    let out = str_var_subst(
+        &["--check", "--vars-file", vars_file.to_str().unwrap()],
+        template,
+    );
+    assert!(out.status.success(), "{:?}", out);
+    let vars_file_name = vars_file.to_str().unwrap();
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        format!(
+            "<stdin>:\n  1:1: %{{{{name}}}} resolved from {}\n  1:11: %{{{{STR_VAR_SUBST_CLI_SET}}}} resolved from environment\n  1:38: %{{{{tag}}}} uses the default value \"latest\"\n  2:1: %{{{{STR_VAR_SUBST_CLI_UNSET}}}} is unset, replaced with an empty string\n",
+            vars_file_name
+        )
+    );
+
+    let out = str_var_subst(&["--check", "--strict", "--format", "json"], template);
+    assert_eq!(out.status.code(), Some(1));
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.starts_with(r#"[{"template": "<stdin>", "variables": [{"name": "name", "line": 1, "column": 1, "status": "empty"}"#));
+    assert!(stdout.contains(r#""status": "resolved", "source": "environment"}"#));
+
+    fs::write(
+        dir.join("a.json.in"),
+        "%{{STR_VAR_SUBST_CLI_UNSET:?needed}}",
+    )
+    .unwrap();
+    let out = str_var_subst(&["--check", "-r", dir.to_str().unwrap()], "");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8(out.stdout)
+        .unwrap()
+        .contains("is missing: needed"));
+    assert!(!dir.join("a.json").exists());
+    fs::remove_dir_all(dir).unwrap();
+
+    let out = str_var_subst(
+        &["--check", "--strict"],
+        "%{{#if STR_VAR_SUBST_CLI_UNSET}}%{{cert}}%{{/if}}",
+    );
+    assert!(out.status.success(), "{:?}", out);
+}